@@ -9,21 +9,52 @@ fn main() {
         // Only generate bindings for actual VA-API items
         // .allowlist_file(r".*/va/va.*\.h")
         // .allowlist_type("VA.*")
+        .allowlist_var("VA_ATTRIB_NOT_SUPPORTED")
+        .allowlist_var("VA_DEC_SLICE_MODE_.*")
+        .allowlist_var("VA_DISPLAY_ATTRIB_.*")
+        .allowlist_var("VA_ENC_INTERLACED_.*")
+        .allowlist_var("VA_EXPORT_SURFACE_.*")
+        .allowlist_var("VA_FOURCC_.*")
+        .allowlist_var("VA_LSB_FIRST")
+        .allowlist_var("VA_MSB_FIRST")
+        .allowlist_var("VA_RC_.*")
+        .allowlist_var("VA_RT_FORMAT_.*")
         .allowlist_var("VA_STATUS_.*")
+        .allowlist_var("VA_SURFACE_ATTRIB_MEM_TYPE_.*")
         .allowlist_type("VABufferID")
         .allowlist_type("VABufferType")
         .allowlist_type("VAConfigAttrib")
+        .allowlist_type("VAConfigAttribType")
         .allowlist_type("VAConfigID")
         .allowlist_type("VAContextID")
+        .allowlist_type("VADRMPRIMESurfaceDescriptor")
+        .allowlist_type("VADecPictureParameterBufferAV1")
+        .allowlist_type("VADecPictureParameterBufferVP9")
+        .allowlist_type("VADisplayAttribType")
         .allowlist_type("VADisplayAttribute")
         .allowlist_type("VADriverContextP")
         .allowlist_type("VADriverInit")
         .allowlist_type("VADriverVTable")
+        .allowlist_type("VAEncPackedHeaderParameterBuffer")
+        .allowlist_type("VAEncPictureParameterBufferH264")
+        .allowlist_type("VAEncPictureParameterBufferHEVC")
+        .allowlist_type("VAEncSequenceParameterBufferH264")
+        .allowlist_type("VAEncSequenceParameterBufferHEVC")
+        .allowlist_type("VAEncSliceParameterBufferH264")
+        .allowlist_type("VAEncSliceParameterBufferHEVC")
         .allowlist_type("VAEntrypoint")
+        .allowlist_type("VAIQMatrixBufferH264")
+        .allowlist_type("VAIQMatrixBufferHEVC")
         .allowlist_type("VAImage")
         .allowlist_type("VAImageFormat")
         .allowlist_type("VAImageID")
+        .allowlist_type("VAPictureParameterBufferH264")
+        .allowlist_type("VAPictureParameterBufferHEVC")
         .allowlist_type("VAProfile")
+        .allowlist_type("VASliceParameterBufferAV1")
+        .allowlist_type("VASliceParameterBufferH264")
+        .allowlist_type("VASliceParameterBufferHEVC")
+        .allowlist_type("VASliceParameterBufferVP9")
         .allowlist_type("VAStatus")
         .allowlist_type("VASubpictureID")
         .allowlist_type("VASurfaceID")
@@ -33,9 +64,10 @@ fn main() {
         // The backend doesn't actually link to libva, so we can ignore functions
         .ignore_functions()
         .ignore_methods()
-        // Disable layout tests to reduce build time
-        // TODO: re-enable?
-        .layout_tests(false)
+        // The layout tests are compile-time assertions against the struct layouts clang computes
+        // for the target (bindgen passes $TARGET on to clang), so they catch size and alignment
+        // mismatches of e.g. the c_ulong/pointer members when cross-compiling for i686.
+        .layout_tests(true)
         // Tell cargo to invalidate the built crate whenever any of the
         // included header files changed.
         .parse_callbacks(Box::new(bindgen::CargoCallbacks::new()))
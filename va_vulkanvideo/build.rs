@@ -0,0 +1,54 @@
+use std::{env, process::Command};
+
+/// Cargo features that are reported in the build info, in the order of `Cargo.toml`.
+const FEATURES: [&str; 6] = ["h264", "h265", "av1", "vp9", "encode", "vpp"];
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    Some(stdout.trim().to_owned())
+}
+
+fn main() {
+    // libva dlcloses the driver when the last display is terminated, and some hosts do it with
+    // displays still alive. Keep the library mapped, so that TLS destructors registered by us or
+    // our dependencies, and threads still running our code, never jump into unmapped memory.
+    // Mesa links its drivers the same way.
+    println!("cargo:rustc-cdylib-link-arg=-Wl,-z,nodelete");
+
+    // The commit and features end up in the vendor string, see build_info.rs. Builds from a
+    // source tarball don't have a commit.
+    let commit = git(&["rev-parse", "--short=12", "HEAD"]);
+    println!(
+        "cargo:rustc-env=VAVK_GIT_COMMIT={}",
+        commit.as_deref().unwrap_or("unknown")
+    );
+    let features: Vec<_> = FEATURES
+        .into_iter()
+        .filter(|feature| {
+            env::var_os(format!("CARGO_FEATURE_{}", feature.to_uppercase())).is_some()
+        })
+        .collect();
+    println!("cargo:rustc-env=VAVK_FEATURES={}", features.join(" "));
+
+    // Feature changes rerun this anyway, but new commits don't touch the package. They move the
+    // branch HEAD points to, or HEAD itself when it is detached.
+    println!("cargo:rerun-if-changed=build.rs");
+    if commit.is_some() {
+        let branch = git(&["symbolic-ref", "--quiet", "HEAD"]);
+        for git_path in ["HEAD"].into_iter().chain(branch.as_deref()) {
+            let path = git(&[
+                "rev-parse",
+                "--path-format=absolute",
+                "--git-path",
+                git_path,
+            ]);
+            if let Some(path) = path {
+                println!("cargo:rerun-if-changed={path}");
+            }
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! Logging of the attribute lists exchanged with the client, enabled with `VAVK_DUMP_ATTRIBS=1`.
+//!
+//! Most negotiation failures come down to a client asking for an attribute value we don't
+//! support, or misreading what we returned. The dump shows both sides with attribute names, so a
+//! single log is enough to tell which.
+// TODO: Also dump the filter and pipeline attributes of VPP once vaQueryVideoProcFilterCaps and
+// vaQueryVideoProcPipelineCaps are implemented.
+
+use std::{borrow::Cow, fmt::Write, sync::LazyLock};
+
+use log::info;
+use va_backend_sys::{VAConfigAttrib, VAConfigAttribType, VASurfaceAttrib, VASurfaceAttribType};
+
+use crate::{VaError, config};
+
+/// Environment variable enabling the dump.
+const DUMP_ATTRIBS_ENV: &str = "VAVK_DUMP_ATTRIBS";
+
+static ENABLED: LazyLock<bool> =
+    LazyLock::new(|| std::env::var_os(DUMP_ATTRIBS_ENV).is_some_and(|value| value == "1"));
+
+/// Whether attribute lists should be dumped. Check this before collecting anything to dump.
+pub(crate) fn enabled() -> bool {
+    *ENABLED
+}
+
+fn config_attrib_name(type_: VAConfigAttribType) -> Cow<'static, str> {
+    let name = match type_ {
+        va_backend_sys::VAConfigAttribType_VAConfigAttribRTFormat => "RTFormat",
+        va_backend_sys::VAConfigAttribType_VAConfigAttribRateControl => "RateControl",
+        va_backend_sys::VAConfigAttribType_VAConfigAttribDecSliceMode => "DecSliceMode",
+        va_backend_sys::VAConfigAttribType_VAConfigAttribDecProcessing => "DecProcessing",
+        va_backend_sys::VAConfigAttribType_VAConfigAttribEncPackedHeaders => "EncPackedHeaders",
+        va_backend_sys::VAConfigAttribType_VAConfigAttribEncInterlaced => "EncInterlaced",
+        va_backend_sys::VAConfigAttribType_VAConfigAttribEncMaxRefFrames => "EncMaxRefFrames",
+        va_backend_sys::VAConfigAttribType_VAConfigAttribEncMaxSlices => "EncMaxSlices",
+        va_backend_sys::VAConfigAttribType_VAConfigAttribEncSliceStructure => "EncSliceStructure",
+        va_backend_sys::VAConfigAttribType_VAConfigAttribMaxPictureWidth => "MaxPictureWidth",
+        va_backend_sys::VAConfigAttribType_VAConfigAttribMaxPictureHeight => "MaxPictureHeight",
+        va_backend_sys::VAConfigAttribType_VAConfigAttribEncQualityRange => "EncQualityRange",
+        va_backend_sys::VAConfigAttribType_VAConfigAttribEncIntraRefresh => "EncIntraRefresh",
+        config::CONFIG_ATTRIB_DPB_MODE => "DpbMode (driver)",
+        config::CONFIG_ATTRIB_SOFTWARE_CSC => "SoftwareCsc (driver)",
+        config::CONFIG_ATTRIB_ENTROPY_MODES => "EntropyModes (driver)",
+        _ => return format!("{type_}").into(),
+    };
+    name.into()
+}
+
+fn surface_attrib_name(type_: VASurfaceAttribType) -> Cow<'static, str> {
+    let name = match type_ {
+        va_backend_sys::VASurfaceAttribType_VASurfaceAttribPixelFormat => "PixelFormat",
+        va_backend_sys::VASurfaceAttribType_VASurfaceAttribMinWidth => "MinWidth",
+        va_backend_sys::VASurfaceAttribType_VASurfaceAttribMaxWidth => "MaxWidth",
+        va_backend_sys::VASurfaceAttribType_VASurfaceAttribMinHeight => "MinHeight",
+        va_backend_sys::VASurfaceAttribType_VASurfaceAttribMaxHeight => "MaxHeight",
+        va_backend_sys::VASurfaceAttribType_VASurfaceAttribMemoryType => "MemoryType",
+        va_backend_sys::VASurfaceAttribType_VASurfaceAttribExternalBufferDescriptor => {
+            "ExternalBufferDescriptor"
+        }
+        va_backend_sys::VASurfaceAttribType_VASurfaceAttribUsageHint => "UsageHint",
+        va_backend_sys::VASurfaceAttribType_VASurfaceAttribDRMFormatModifiers => {
+            "DRMFormatModifiers"
+        }
+        _ => return format!("{type_}").into(),
+    };
+    name.into()
+}
+
+/// Logs config attributes, e.g. the list passed to vaCreateConfig and the one it resulted in.
+pub(crate) fn config_attribs(call: &str, list: &str, attribs: &[VAConfigAttrib]) {
+    let mut dump = String::new();
+    for attrib in attribs {
+        let name = config_attrib_name(attrib.type_);
+        if attrib.value == va_backend_sys::VA_ATTRIB_NOT_SUPPORTED {
+            let _ = write!(dump, " {name}=<not supported>");
+        } else {
+            let _ = write!(dump, " {name}={:#x}", attrib.value);
+        }
+    }
+    info!("{call} {list}:{dump}");
+}
+
+/// Logs surface attributes, e.g. the list passed to vaCreateSurfaces2.
+pub(crate) fn surface_attribs(call: &str, list: &str, attribs: &[VASurfaceAttrib]) {
+    let mut dump = String::new();
+    for attrib in attribs {
+        let name = surface_attrib_name(attrib.type_);
+        // SAFETY: The union member is selected by the value type.
+        let _ = match attrib.value.type_ {
+            va_backend_sys::VAGenericValueType_VAGenericValueTypeInteger => {
+                write!(dump, " {name}={:#x}", unsafe { attrib.value.value.i })
+            }
+            va_backend_sys::VAGenericValueType_VAGenericValueTypeFloat => {
+                write!(dump, " {name}={}", unsafe { attrib.value.value.f })
+            }
+            va_backend_sys::VAGenericValueType_VAGenericValueTypePointer => {
+                write!(dump, " {name}={:p}", unsafe { attrib.value.value.p })
+            }
+            other => write!(dump, " {name}=<value type {other}>"),
+        };
+    }
+    info!("{call} {list}:{dump}");
+}
+
+/// Logs the status a call returned to the client.
+pub(crate) fn result<T>(call: &str, result: &Result<T, VaError>) {
+    match result {
+        Ok(_) => info!("{call} succeeded"),
+        Err(err) => info!("{call} failed with {err}"),
+    }
+}
@@ -0,0 +1,259 @@
+//! Framing of AV1 OBUs in slice data buffers.
+//!
+//! Clients pass the bytes from the container through untouched, so depending on the demuxer the
+//! data is either in the low-overhead bitstream format (AV1 spec section 5, MP4/WebM/IVF), where
+//! every OBU carries its size in the header, or in the length-delimited Annex B format (MPEG-TS),
+//! where temporal units, frame units and OBUs are prefixed by their lengths instead. Vulkan AV1
+//! decode expects the low-overhead format, so Annex B data is rewritten before it is uploaded.
+
+// Not wired up until AV1 decode is implemented.
+#![allow(dead_code)]
+
+use std::borrow::Cow;
+
+use crate::VaError;
+
+/// `obu_reserved_1bit` in the first OBU header byte, always 0.
+const OBU_RESERVED_BIT: u8 = 0x01;
+/// `obu_has_size_field` in the first OBU header byte.
+const OBU_HAS_SIZE_FIELD: u8 = 0x02;
+/// `obu_extension_flag` in the first OBU header byte.
+const OBU_EXTENSION_FLAG: u8 = 0x04;
+/// `obu_forbidden_bit` in the first OBU header byte.
+const OBU_FORBIDDEN_BIT: u8 = 0x80;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Framing {
+    /// Low-overhead bitstream format, every OBU has `obu_has_size_field` set.
+    LowOverhead,
+    /// Length-delimited bitstream format.
+    AnnexB,
+}
+
+/// Reads a `leb128()` value, returning it and the number of bytes it took.
+fn read_leb128(data: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    for (i, &byte) in data.iter().take(8).enumerate() {
+        value |= u64::from(byte & 0x7f) << (i * 7);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+fn write_leb128(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Splits a length-prefixed unit off the front of `data`, returning it and the rest.
+fn split_sized(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (size, len) = read_leb128(data)?;
+    let size = usize::try_from(size).ok()?;
+    let rest = &data[len..];
+    (size <= rest.len()).then(|| rest.split_at(size))
+}
+
+/// Length of the OBU header (including the extension) starting at `data[0]`.
+fn obu_header_len(data: &[u8]) -> Option<usize> {
+    let first = *data.first()?;
+    if first & (OBU_FORBIDDEN_BIT | OBU_RESERVED_BIT) != 0 {
+        return None;
+    }
+    let len = if first & OBU_EXTENSION_FLAG != 0 {
+        2
+    } else {
+        1
+    };
+    (data.len() >= len).then_some(len)
+}
+
+/// Whether `data` is a sequence of low-overhead OBUs that ends exactly at the end of the buffer.
+fn is_low_overhead(mut data: &[u8]) -> bool {
+    while !data.is_empty() {
+        let Some(header_len) = obu_header_len(data) else {
+            return false;
+        };
+        if data[0] & OBU_HAS_SIZE_FIELD == 0 {
+            return false;
+        }
+        // Decoders have to skip OBUs of reserved types, but the size prefixes of Annex B data
+        // often look like such OBU headers. Requiring known types makes misdetection much less
+        // likely, at the cost of rejecting low-overhead data with reserved OBUs.
+        if matches!((data[0] >> 3) & 0xf, 0 | 9..=14) {
+            return false;
+        }
+        let Some((_, rest)) = split_sized(&data[header_len..]) else {
+            return false;
+        };
+        data = rest;
+    }
+    true
+}
+
+/// Calls `f` with each OBU of Annex B `data`, or returns `None` if the lengths don't add up.
+fn for_each_annexb_obu(mut data: &[u8], mut f: impl FnMut(&[u8])) -> Option<()> {
+    while !data.is_empty() {
+        let (mut temporal_unit, rest) = split_sized(data)?;
+        data = rest;
+        while !temporal_unit.is_empty() {
+            let (mut frame_unit, rest) = split_sized(temporal_unit)?;
+            temporal_unit = rest;
+            while !frame_unit.is_empty() {
+                let (obu, rest) = split_sized(frame_unit)?;
+                frame_unit = rest;
+                obu_header_len(obu)?;
+                f(obu);
+            }
+        }
+    }
+    Some(())
+}
+
+/// Detects the framing of `data`. Low-overhead is checked first, as that is what most clients
+/// pass, and an Annex B stream is very unlikely to also parse as valid low-overhead OBUs.
+pub(crate) fn detect(data: &[u8]) -> Option<Framing> {
+    if is_low_overhead(data) {
+        Some(Framing::LowOverhead)
+    } else if for_each_annexb_obu(data, |_| {}).is_some() {
+        Some(Framing::AnnexB)
+    } else {
+        None
+    }
+}
+
+/// Returns `data` in the low-overhead format, converting it if it is in the Annex B format.
+pub(crate) fn to_low_overhead(data: &[u8]) -> Result<Cow<'_, [u8]>, VaError> {
+    match detect(data) {
+        Some(Framing::LowOverhead) => Ok(Cow::Borrowed(data)),
+        Some(Framing::AnnexB) => {
+            let mut out = Vec::with_capacity(data.len());
+            for_each_annexb_obu(data, |obu| {
+                let header_len = obu_header_len(obu).expect("checked while detecting");
+                // The size field is optional in Annex B, skip the old one if present
+                let mut payload = &obu[header_len..];
+                if obu[0] & OBU_HAS_SIZE_FIELD != 0
+                    && let Some((size, len)) = read_leb128(payload)
+                {
+                    let end = (len as u64).saturating_add(size).min(payload.len() as u64);
+                    payload = &payload[len..end as usize];
+                }
+                out.push(obu[0] | OBU_HAS_SIZE_FIELD);
+                out.extend_from_slice(&obu[1..header_len]);
+                write_leb128(&mut out, payload.len() as u64);
+                out.extend_from_slice(payload);
+            })
+            .expect("checked while detecting");
+            Ok(Cow::Owned(out))
+        }
+        None => Err(VaError::InvalidParameter.context("AV1 data has neither OBU framing")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use va_backend_sys::VAStatus;
+
+    use super::*;
+
+    /// Temporal delimiter without a size field.
+    const TEMPORAL_DELIMITER: [u8; 1] = [0x10];
+    /// Frame OBU with an extension header (temporal ID 0, spatial ID 1), without a size field.
+    const FRAME: [u8; 5] = [0x34, 0x08, 1, 2, 3];
+
+    /// Prefixes `unit` with its size.
+    fn sized(unit: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_leb128(&mut out, unit.len() as u64);
+        out.extend_from_slice(unit);
+        out
+    }
+
+    /// A single temporal unit with a single frame unit holding `obus`.
+    fn annexb(obus: &[&[u8]]) -> Vec<u8> {
+        let frame_unit: Vec<u8> = obus.iter().flat_map(|obu| sized(obu)).collect();
+        sized(&sized(&frame_unit))
+    }
+
+    fn status(result: Result<Cow<'_, [u8]>, VaError>) -> VAStatus {
+        result.map_or_else(VAStatus::from, |_| {
+            va_backend_sys::VA_STATUS_SUCCESS as VAStatus
+        })
+    }
+
+    #[test]
+    fn leb128_round_trip() {
+        for value in [0, 1, 0x7f, 0x80, 300, u64::from(u32::MAX)] {
+            let mut encoded = Vec::new();
+            write_leb128(&mut encoded, value);
+            assert_eq!(read_leb128(&encoded), Some((value, encoded.len())));
+        }
+    }
+
+    #[test]
+    fn leb128_truncated() {
+        assert_eq!(read_leb128(&[]), None);
+        assert_eq!(read_leb128(&[0x80]), None);
+        // At most 8 bytes
+        assert_eq!(read_leb128(&[0x80; 9]), None);
+    }
+
+    #[test]
+    fn low_overhead_passthrough() {
+        let data = [0x12, 0x00, 0x36, 0x08, 0x03, 1, 2, 3];
+        assert_eq!(detect(&data), Some(Framing::LowOverhead));
+        let converted = to_low_overhead(&data).unwrap();
+        assert!(matches!(converted, Cow::Borrowed(_)));
+        assert_eq!(&*converted, &data);
+    }
+
+    #[test]
+    fn annexb_without_size_field() {
+        let data = annexb(&[&TEMPORAL_DELIMITER, &FRAME]);
+        assert_eq!(detect(&data), Some(Framing::AnnexB));
+        assert_eq!(
+            &*to_low_overhead(&data).unwrap(),
+            &[0x12, 0x00, 0x36, 0x08, 0x03, 1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn annexb_with_size_field() {
+        let data = annexb(&[&[0x36, 0x08, 0x03, 1, 2, 3]]);
+        assert_eq!(detect(&data), Some(Framing::AnnexB));
+        assert_eq!(
+            &*to_low_overhead(&data).unwrap(),
+            &[0x36, 0x08, 0x03, 1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn annexb_trailing_bytes_after_size_field() {
+        // The OBU size says 1, the rest of the OBU length is trailing data
+        let data = annexb(&[&[0x32, 0x01, 0xaa, 0xbb]]);
+        assert_eq!(&*to_low_overhead(&data).unwrap(), &[0x32, 0x01, 0xaa]);
+    }
+
+    #[test]
+    fn malformed() {
+        let invalid = VAStatus::from(VaError::InvalidParameter);
+        // Temporal unit longer than the data
+        assert_eq!(status(to_low_overhead(&[0x05, 0x01, 0x00])), invalid);
+        // Truncated size
+        assert_eq!(status(to_low_overhead(&[0x80])), invalid);
+        // OBU header with the forbidden bit set
+        assert_eq!(status(to_low_overhead(&annexb(&[&[0x90]]))), invalid);
+        // Extension flag set, but the extension byte is missing
+        assert_eq!(status(to_low_overhead(&annexb(&[&[0x34]]))), invalid);
+        // Low-overhead OBU whose size runs past the end
+        assert_eq!(status(to_low_overhead(&[0x32, 0x05, 1, 2])), invalid);
+    }
+}
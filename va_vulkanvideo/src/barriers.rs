@@ -0,0 +1,396 @@
+//! Layout transitions and queue family ownership transfers for images shared between queues.
+//!
+//! Surfaces move between the decode, encode, compute (VPP) and transfer queues. With
+//! `VK_SHARING_MODE_EXCLUSIVE`, the contents of an image are undefined on a new queue family
+//! unless its ownership is released on the old queue and acquired on the new one, which shows up
+//! as corrupted frames in mixed pipelines. [`ImageTracker`] remembers the last use of an image
+//! and produces the barriers needed for the next one.
+
+// Not wired up until surfaces are allocated.
+#![allow(dead_code)]
+
+use ash::vk;
+
+/// Accesses that modify an image. Everything else is a read.
+const WRITE_ACCESSES: vk::AccessFlags2 = vk::AccessFlags2::from_raw(
+    vk::AccessFlags2::SHADER_WRITE.as_raw()
+        | vk::AccessFlags2::SHADER_STORAGE_WRITE.as_raw()
+        | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE.as_raw()
+        | vk::AccessFlags2::TRANSFER_WRITE.as_raw()
+        | vk::AccessFlags2::HOST_WRITE.as_raw()
+        | vk::AccessFlags2::MEMORY_WRITE.as_raw()
+        | vk::AccessFlags2::VIDEO_DECODE_WRITE_KHR.as_raw()
+        | vk::AccessFlags2::VIDEO_ENCODE_WRITE_KHR.as_raw(),
+);
+
+/// Picks the sharing mode for an image used on `queue_families`.
+///
+/// CONCURRENT avoids ownership transfers altogether, but may disable compression on some
+/// implementations. It's therefore only used for linear images, which aren't compressed anyway.
+pub(crate) fn sharing_mode(queue_families: &[u32], tiling: vk::ImageTiling) -> vk::SharingMode {
+    let mut unique = queue_families.to_vec();
+    unique.sort_unstable();
+    unique.dedup();
+
+    if unique.len() > 1 && tiling == vk::ImageTiling::LINEAR {
+        vk::SharingMode::CONCURRENT
+    } else {
+        vk::SharingMode::EXCLUSIVE
+    }
+}
+
+/// A use of an image on a specific queue family.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct ImageUse {
+    pub(crate) queue_family: u32,
+    pub(crate) layout: vk::ImageLayout,
+    pub(crate) stage: vk::PipelineStageFlags2,
+    pub(crate) access: vk::AccessFlags2,
+}
+
+/// Barriers required before an image can be used in a new way.
+#[derive(Debug)]
+pub(crate) enum Transition {
+    /// The image can be used as-is.
+    None,
+    /// A barrier to record on the queue of the new use, or on the queue of the previous use if
+    /// the image is being released to `VK_QUEUE_FAMILY_EXTERNAL`.
+    Barrier(vk::ImageMemoryBarrier2<'static>),
+    /// An ownership transfer: `release` must be recorded on the queue of the previous use,
+    /// `acquire` on the queue of the new use, and the two submissions ordered by a semaphore.
+    OwnershipTransfer {
+        release: vk::ImageMemoryBarrier2<'static>,
+        acquire: vk::ImageMemoryBarrier2<'static>,
+    },
+}
+
+/// Tracks the last use of an image.
+#[derive(Debug)]
+pub(crate) struct ImageTracker {
+    image: vk::Image,
+    range: vk::ImageSubresourceRange,
+    sharing_mode: vk::SharingMode,
+    /// `None` until the image is first used, its contents are undefined until then.
+    last_use: Option<ImageUse>,
+}
+
+impl ImageTracker {
+    pub(crate) fn new(
+        image: vk::Image,
+        range: vk::ImageSubresourceRange,
+        sharing_mode: vk::SharingMode,
+    ) -> Self {
+        Self {
+            image,
+            range,
+            sharing_mode,
+            last_use: None,
+        }
+    }
+
+    /// Records `next` as the new use of the image and returns the barriers needed before it.
+    ///
+    /// If `discard` is set, the previous contents aren't needed (e.g. because the image is about
+    /// to be fully overwritten), which skips the ownership transfer.
+    pub(crate) fn transition(&mut self, next: ImageUse, discard: bool) -> Transition {
+        let Some(prev) = self.last_use.replace(next) else {
+            return Transition::Barrier(self.barrier(
+                (vk::PipelineStageFlags2::NONE, vk::AccessFlags2::NONE),
+                (next.stage, next.access),
+                (vk::ImageLayout::UNDEFINED, next.layout),
+                (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED),
+            ));
+        };
+
+        let read_only =
+            !prev.access.intersects(WRITE_ACCESSES) && !next.access.intersects(WRITE_ACCESSES);
+        if prev == next && read_only {
+            return Transition::None;
+        }
+
+        let old_layout = if discard {
+            vk::ImageLayout::UNDEFINED
+        } else {
+            prev.layout
+        };
+
+        let same_owner = prev.queue_family == next.queue_family
+            || self.sharing_mode == vk::SharingMode::CONCURRENT;
+        if same_owner || discard {
+            return Transition::Barrier(self.barrier(
+                (prev.stage, prev.access),
+                (next.stage, next.access),
+                (old_layout, next.layout),
+                (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED),
+            ));
+        }
+
+        // The layout transition has to be specified identically in both barriers, it is only
+        // executed once.
+        let queue_families = (prev.queue_family, next.queue_family);
+        let release = self.barrier(
+            (prev.stage, prev.access),
+            (vk::PipelineStageFlags2::NONE, vk::AccessFlags2::NONE),
+            (old_layout, next.layout),
+            queue_families,
+        );
+        let acquire = self.barrier(
+            (vk::PipelineStageFlags2::NONE, vk::AccessFlags2::NONE),
+            (next.stage, next.access),
+            (old_layout, next.layout),
+            queue_families,
+        );
+        // The importer of an exported image does its own half of the transfer
+        if next.queue_family == vk::QUEUE_FAMILY_EXTERNAL {
+            Transition::Barrier(release)
+        } else if prev.queue_family == vk::QUEUE_FAMILY_EXTERNAL {
+            Transition::Barrier(acquire)
+        } else {
+            Transition::OwnershipTransfer { release, acquire }
+        }
+    }
+
+    fn barrier(
+        &self,
+        (src_stage, src_access): (vk::PipelineStageFlags2, vk::AccessFlags2),
+        (dst_stage, dst_access): (vk::PipelineStageFlags2, vk::AccessFlags2),
+        (old_layout, new_layout): (vk::ImageLayout, vk::ImageLayout),
+        (src_queue_family, dst_queue_family): (u32, u32),
+    ) -> vk::ImageMemoryBarrier2<'static> {
+        vk::ImageMemoryBarrier2::default()
+            .src_stage_mask(src_stage)
+            .src_access_mask(src_access)
+            .dst_stage_mask(dst_stage)
+            .dst_access_mask(dst_access)
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_queue_family_index(src_queue_family)
+            .dst_queue_family_index(dst_queue_family)
+            .image(self.image)
+            .subresource_range(self.range)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DECODE_QUEUE: u32 = 1;
+    const COMPUTE_QUEUE: u32 = 2;
+
+    fn tracker(sharing_mode: vk::SharingMode) -> ImageTracker {
+        ImageTracker::new(
+            vk::Image::null(),
+            vk::ImageSubresourceRange::default()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .level_count(1)
+                .layer_count(1),
+            sharing_mode,
+        )
+    }
+
+    fn decode() -> ImageUse {
+        ImageUse {
+            queue_family: DECODE_QUEUE,
+            layout: vk::ImageLayout::VIDEO_DECODE_DST_KHR,
+            stage: vk::PipelineStageFlags2::VIDEO_DECODE_KHR,
+            access: vk::AccessFlags2::VIDEO_DECODE_WRITE_KHR,
+        }
+    }
+
+    fn sample(queue_family: u32) -> ImageUse {
+        ImageUse {
+            queue_family,
+            layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            stage: vk::PipelineStageFlags2::COMPUTE_SHADER,
+            access: vk::AccessFlags2::SHADER_SAMPLED_READ,
+        }
+    }
+
+    fn barrier(transition: Transition) -> vk::ImageMemoryBarrier2<'static> {
+        match transition {
+            Transition::Barrier(barrier) => barrier,
+            other => panic!("expected a single barrier, got {other:?}"),
+        }
+    }
+
+    fn assert_layouts(
+        barrier: &vk::ImageMemoryBarrier2,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+    ) {
+        assert_eq!(barrier.old_layout, old_layout);
+        assert_eq!(barrier.new_layout, new_layout);
+    }
+
+    fn assert_queue_families(barrier: &vk::ImageMemoryBarrier2, src: u32, dst: u32) {
+        assert_eq!(barrier.src_queue_family_index, src);
+        assert_eq!(barrier.dst_queue_family_index, dst);
+    }
+
+    #[test]
+    fn sharing_mode_only_concurrent_for_linear() {
+        let families = [DECODE_QUEUE, COMPUTE_QUEUE];
+        assert_eq!(
+            sharing_mode(&families, vk::ImageTiling::LINEAR),
+            vk::SharingMode::CONCURRENT
+        );
+        assert_eq!(
+            sharing_mode(&families, vk::ImageTiling::OPTIMAL),
+            vk::SharingMode::EXCLUSIVE
+        );
+        assert_eq!(
+            sharing_mode(&[DECODE_QUEUE, DECODE_QUEUE], vk::ImageTiling::LINEAR),
+            vk::SharingMode::EXCLUSIVE
+        );
+    }
+
+    #[test]
+    fn first_use() {
+        let mut tracker = tracker(vk::SharingMode::EXCLUSIVE);
+        let barrier = barrier(tracker.transition(decode(), false));
+        assert_layouts(
+            &barrier,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::VIDEO_DECODE_DST_KHR,
+        );
+        assert_queue_families(&barrier, vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED);
+        assert_eq!(barrier.src_stage_mask, vk::PipelineStageFlags2::NONE);
+        assert_eq!(
+            barrier.dst_stage_mask,
+            vk::PipelineStageFlags2::VIDEO_DECODE_KHR
+        );
+    }
+
+    #[test]
+    fn repeated_read_is_a_no_op() {
+        let mut tracker = tracker(vk::SharingMode::EXCLUSIVE);
+        tracker.transition(sample(COMPUTE_QUEUE), false);
+        assert!(matches!(
+            tracker.transition(sample(COMPUTE_QUEUE), false),
+            Transition::None
+        ));
+    }
+
+    #[test]
+    fn repeated_write_needs_a_barrier() {
+        let mut tracker = tracker(vk::SharingMode::EXCLUSIVE);
+        tracker.transition(decode(), false);
+        let barrier = barrier(tracker.transition(decode(), false));
+        assert_layouts(
+            &barrier,
+            vk::ImageLayout::VIDEO_DECODE_DST_KHR,
+            vk::ImageLayout::VIDEO_DECODE_DST_KHR,
+        );
+        assert_eq!(
+            barrier.src_access_mask,
+            vk::AccessFlags2::VIDEO_DECODE_WRITE_KHR
+        );
+    }
+
+    #[test]
+    fn same_queue_family() {
+        let mut tracker = tracker(vk::SharingMode::EXCLUSIVE);
+        tracker.transition(decode(), false);
+        let barrier = barrier(tracker.transition(sample(DECODE_QUEUE), false));
+        assert_layouts(
+            &barrier,
+            vk::ImageLayout::VIDEO_DECODE_DST_KHR,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+        assert_queue_families(&barrier, vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED);
+    }
+
+    #[test]
+    fn discard_skips_the_ownership_transfer() {
+        let mut tracker = tracker(vk::SharingMode::EXCLUSIVE);
+        tracker.transition(sample(COMPUTE_QUEUE), false);
+        let barrier = barrier(tracker.transition(decode(), true));
+        assert_layouts(
+            &barrier,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::VIDEO_DECODE_DST_KHR,
+        );
+        assert_queue_families(&barrier, vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED);
+    }
+
+    #[test]
+    fn concurrent_skips_the_ownership_transfer() {
+        let mut tracker = tracker(vk::SharingMode::CONCURRENT);
+        tracker.transition(decode(), false);
+        let barrier = barrier(tracker.transition(sample(COMPUTE_QUEUE), false));
+        assert_layouts(
+            &barrier,
+            vk::ImageLayout::VIDEO_DECODE_DST_KHR,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+        assert_queue_families(&barrier, vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED);
+    }
+
+    #[test]
+    fn exclusive_ownership_transfer() {
+        let mut tracker = tracker(vk::SharingMode::EXCLUSIVE);
+        tracker.transition(decode(), false);
+        let Transition::OwnershipTransfer { release, acquire } =
+            tracker.transition(sample(COMPUTE_QUEUE), false)
+        else {
+            panic!("expected an ownership transfer");
+        };
+
+        for barrier in [&release, &acquire] {
+            assert_layouts(
+                barrier,
+                vk::ImageLayout::VIDEO_DECODE_DST_KHR,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            );
+            assert_queue_families(barrier, DECODE_QUEUE, COMPUTE_QUEUE);
+        }
+        assert_eq!(
+            release.src_stage_mask,
+            vk::PipelineStageFlags2::VIDEO_DECODE_KHR
+        );
+        assert_eq!(release.dst_stage_mask, vk::PipelineStageFlags2::NONE);
+        assert_eq!(acquire.src_stage_mask, vk::PipelineStageFlags2::NONE);
+        assert_eq!(
+            acquire.dst_stage_mask,
+            vk::PipelineStageFlags2::COMPUTE_SHADER
+        );
+    }
+
+    #[test]
+    fn release_to_external() {
+        let mut tracker = tracker(vk::SharingMode::EXCLUSIVE);
+        tracker.transition(decode(), false);
+        let export = ImageUse {
+            queue_family: vk::QUEUE_FAMILY_EXTERNAL,
+            layout: vk::ImageLayout::GENERAL,
+            stage: vk::PipelineStageFlags2::NONE,
+            access: vk::AccessFlags2::NONE,
+        };
+        let release = barrier(tracker.transition(export, false));
+        assert_layouts(
+            &release,
+            vk::ImageLayout::VIDEO_DECODE_DST_KHR,
+            vk::ImageLayout::GENERAL,
+        );
+        assert_queue_families(&release, DECODE_QUEUE, vk::QUEUE_FAMILY_EXTERNAL);
+        assert_eq!(
+            release.src_stage_mask,
+            vk::PipelineStageFlags2::VIDEO_DECODE_KHR
+        );
+
+        // Getting it back acquires it from the external queue family
+        let acquire = barrier(tracker.transition(decode(), false));
+        assert_layouts(
+            &acquire,
+            vk::ImageLayout::GENERAL,
+            vk::ImageLayout::VIDEO_DECODE_DST_KHR,
+        );
+        assert_queue_families(&acquire, vk::QUEUE_FAMILY_EXTERNAL, DECODE_QUEUE);
+        assert_eq!(acquire.src_stage_mask, vk::PipelineStageFlags2::NONE);
+        assert_eq!(
+            acquire.dst_stage_mask,
+            vk::PipelineStageFlags2::VIDEO_DECODE_KHR
+        );
+    }
+}
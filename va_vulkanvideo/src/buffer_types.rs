@@ -0,0 +1,175 @@
+//! The buffer types accepted by each kind of context.
+//!
+//! vaCreateBuffer only gets a context, a type and a size, and the parameter structures are
+//! reinterpreted from the buffer contents much later, when the picture is recorded. Checking the
+//! type and element size against the profile and entrypoint of the context up front turns client
+//! bugs (e.g. HEVC parameters sent to an H.264 context, or headers from a different libva
+//! version) into an error from the call that caused them, instead of garbage in the bitstream.
+
+// Not wired up until contexts and buffers are implemented.
+#![allow(dead_code)]
+
+use va_backend_sys::{VABufferType, VAEntrypoint, VAProfile};
+
+use crate::{Codec, VaError, va_profile_codec};
+
+/// When the contents of a buffer are used.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Scope {
+    /// Applies to the picture it is rendered with.
+    Picture,
+    /// Stays in effect for the following pictures until replaced.
+    Sequence,
+    /// Written by the driver, e.g. coded buffers. Never passed to vaRenderPicture.
+    Output,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct BufferTypeInfo {
+    /// Name of the element structure, for error messages.
+    pub(crate) name: &'static str,
+    /// Size of each element, or `None` for byte buffers (slice data, packed headers, ...) and
+    /// buffers with variable-size contents.
+    pub(crate) element_size: Option<usize>,
+    pub(crate) scope: Scope,
+}
+
+impl BufferTypeInfo {
+    const fn new<T>(name: &'static str, scope: Scope) -> Self {
+        Self {
+            name,
+            element_size: Some(size_of::<T>()),
+            scope,
+        }
+    }
+
+    const fn bytes(name: &'static str, scope: Scope) -> Self {
+        Self {
+            name,
+            element_size: None,
+            scope,
+        }
+    }
+
+    /// Checks the arguments of vaCreateBuffer against the element size.
+    pub(crate) fn check_size(&self, size: u32, num_elements: u32) -> Result<(), VaError> {
+        if num_elements == 0 {
+            return Err(
+                VaError::InvalidParameter.context(format!("{} buffer without elements", self.name))
+            );
+        }
+        if let Some(element_size) = self.element_size
+            && size as usize != element_size
+        {
+            return Err(VaError::InvalidParameter.context(format!(
+                "{} has {element_size} bytes, but the buffer elements have {size} (libva \
+                 version mismatch?)",
+                self.name,
+            )));
+        }
+        Ok(())
+    }
+
+    /// Checks that buffers of this type may be passed to vaRenderPicture.
+    pub(crate) fn check_render(&self) -> Result<(), VaError> {
+        if self.scope == Scope::Output {
+            return Err(
+                VaError::InvalidBuffer.context(format!("{} buffers can't be rendered", self.name))
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Info for buffers of the VA parameter structure `$ty`.
+macro_rules! params {
+    ($ty:ident, $scope:expr) => {
+        BufferTypeInfo::new::<va_backend_sys::$ty>(stringify!($ty), $scope)
+    };
+}
+
+fn decode_buffer_type(codec: Codec, buffer_type: VABufferType) -> Option<BufferTypeInfo> {
+    const PICTURE_PARAMETER: VABufferType =
+        va_backend_sys::VABufferType_VAPictureParameterBufferType;
+    const IQ_MATRIX: VABufferType = va_backend_sys::VABufferType_VAIQMatrixBufferType;
+    const SLICE_PARAMETER: VABufferType = va_backend_sys::VABufferType_VASliceParameterBufferType;
+    const SLICE_DATA: VABufferType = va_backend_sys::VABufferType_VASliceDataBufferType;
+
+    let info = match (codec, buffer_type) {
+        (_, SLICE_DATA) => BufferTypeInfo::bytes("slice data", Scope::Picture),
+        (Codec::H264, PICTURE_PARAMETER) => params!(VAPictureParameterBufferH264, Scope::Picture),
+        (Codec::H264, IQ_MATRIX) => params!(VAIQMatrixBufferH264, Scope::Picture),
+        (Codec::H264, SLICE_PARAMETER) => params!(VASliceParameterBufferH264, Scope::Picture),
+        (Codec::H265, PICTURE_PARAMETER) => params!(VAPictureParameterBufferHEVC, Scope::Picture),
+        (Codec::H265, IQ_MATRIX) => params!(VAIQMatrixBufferHEVC, Scope::Picture),
+        (Codec::H265, SLICE_PARAMETER) => params!(VASliceParameterBufferHEVC, Scope::Picture),
+        (Codec::Vp9, PICTURE_PARAMETER) => params!(VADecPictureParameterBufferVP9, Scope::Picture),
+        (Codec::Vp9, SLICE_PARAMETER) => params!(VASliceParameterBufferVP9, Scope::Picture),
+        (Codec::Av1, PICTURE_PARAMETER) => params!(VADecPictureParameterBufferAV1, Scope::Picture),
+        (Codec::Av1, SLICE_PARAMETER) => params!(VASliceParameterBufferAV1, Scope::Picture),
+        _ => return None,
+    };
+    Some(info)
+}
+
+fn encode_buffer_type(codec: Codec, buffer_type: VABufferType) -> Option<BufferTypeInfo> {
+    const SEQUENCE_PARAMETER: VABufferType =
+        va_backend_sys::VABufferType_VAEncSequenceParameterBufferType;
+    const PICTURE_PARAMETER: VABufferType =
+        va_backend_sys::VABufferType_VAEncPictureParameterBufferType;
+    const SLICE_PARAMETER: VABufferType =
+        va_backend_sys::VABufferType_VAEncSliceParameterBufferType;
+
+    let info = match (codec, buffer_type) {
+        (_, va_backend_sys::VABufferType_VAEncCodedBufferType) => {
+            BufferTypeInfo::bytes("coded buffer", Scope::Output)
+        }
+        (_, va_backend_sys::VABufferType_VAEncPackedHeaderParameterBufferType) => {
+            params!(VAEncPackedHeaderParameterBuffer, Scope::Picture)
+        }
+        (_, va_backend_sys::VABufferType_VAEncPackedHeaderDataBufferType) => {
+            BufferTypeInfo::bytes("packed header data", Scope::Picture)
+        }
+        // Rate control, frame rate, HRD etc. stay in effect until changed. The size depends on
+        // the misc parameter type in the header.
+        (_, va_backend_sys::VABufferType_VAEncMiscParameterBufferType) => {
+            BufferTypeInfo::bytes("VAEncMiscParameterBuffer", Scope::Sequence)
+        }
+        (Codec::H264, SEQUENCE_PARAMETER) => {
+            params!(VAEncSequenceParameterBufferH264, Scope::Sequence)
+        }
+        (Codec::H264, PICTURE_PARAMETER) => {
+            params!(VAEncPictureParameterBufferH264, Scope::Picture)
+        }
+        (Codec::H264, SLICE_PARAMETER) => params!(VAEncSliceParameterBufferH264, Scope::Picture),
+        (Codec::H265, SEQUENCE_PARAMETER) => {
+            params!(VAEncSequenceParameterBufferHEVC, Scope::Sequence)
+        }
+        (Codec::H265, PICTURE_PARAMETER) => {
+            params!(VAEncPictureParameterBufferHEVC, Scope::Picture)
+        }
+        (Codec::H265, SLICE_PARAMETER) => params!(VAEncSliceParameterBufferHEVC, Scope::Picture),
+        _ => return None,
+    };
+    Some(info)
+}
+
+/// Looks up `buffer_type` for contexts of `profile`/`entrypoint`, failing with
+/// [`VaError::UnsupportedBuffertype`] if such contexts don't accept it.
+pub(crate) fn lookup(
+    profile: VAProfile,
+    entrypoint: VAEntrypoint,
+    buffer_type: VABufferType,
+) -> Result<BufferTypeInfo, VaError> {
+    let info = va_profile_codec(profile).and_then(|codec| match entrypoint {
+        va_backend_sys::VAEntrypoint_VAEntrypointVLD => decode_buffer_type(codec, buffer_type),
+        va_backend_sys::VAEntrypoint_VAEntrypointEncSlice => encode_buffer_type(codec, buffer_type),
+        _ => None,
+    });
+    info.ok_or_else(|| {
+        VaError::UnsupportedBuffertype.context(format!(
+            "buffer type {buffer_type} is not accepted by profile {profile} entrypoint \
+             {entrypoint} contexts"
+        ))
+    })
+}
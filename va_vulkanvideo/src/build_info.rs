@@ -0,0 +1,79 @@
+//! What exactly was built: version, commit and codec features.
+//!
+//! Bug reports usually only contain the output of `vainfo`, and decode-only or codec-restricted
+//! builds look the same as broken hardware from the outside. Everything needed to tell them apart
+//! goes into the vendor string, and the version and features are also exposed as read-only display
+//! attributes, so scripts can check for e.g. AV1 support without parsing it.
+
+use std::ffi::CStr;
+
+/// Name of the driver, without any build details.
+pub(crate) const DRIVER_NAME: &CStr = c"va_vulkan_video";
+
+/// Vendor string reported by vaQueryVendorString, e.g.
+/// `va_vulkan_video 0.1.0 (0123456789ab; h264 h265 av1 vp9 encode vpp)`.
+pub(crate) const VENDOR: &CStr = {
+    let vendor = concat!(
+        "va_vulkan_video ",
+        env!("CARGO_PKG_VERSION"),
+        " (",
+        env!("VAVK_GIT_COMMIT"),
+        "; ",
+        env!("VAVK_FEATURES"),
+        ")\0"
+    );
+    match CStr::from_bytes_with_nul(vendor.as_bytes()) {
+        Ok(vendor) => vendor,
+        Err(_) => panic!("vendor string contains a nul byte"),
+    }
+};
+
+const fn parse_version_part(part: &str) -> u32 {
+    match u32::from_str_radix(part, 10) {
+        Ok(value) if value <= 0xff => value,
+        _ => panic!("version parts must fit into a byte"),
+    }
+}
+
+const MAJOR: u32 = parse_version_part(env!("CARGO_PKG_VERSION_MAJOR"));
+const MINOR: u32 = parse_version_part(env!("CARGO_PKG_VERSION_MINOR"));
+const PATCH: u32 = parse_version_part(env!("CARGO_PKG_VERSION_PATCH"));
+
+/// Crate version as `major << 16 | minor << 8 | patch`.
+pub(crate) const VERSION: i32 = (MAJOR << 16 | MINOR << 8 | PATCH) as i32;
+
+/// Crate version in the encoding of `VK_MAKE_API_VERSION`, for the engine version.
+pub(crate) const VK_VERSION: u32 = ash::vk::make_api_version(0, MAJOR, MINOR, PATCH);
+
+/// Bits of [`FEATURES`].
+const FEATURE_H264: i32 = 1 << 0;
+const FEATURE_H265: i32 = 1 << 1;
+const FEATURE_AV1: i32 = 1 << 2;
+const FEATURE_VP9: i32 = 1 << 3;
+const FEATURE_ENCODE: i32 = 1 << 4;
+const FEATURE_VPP: i32 = 1 << 5;
+
+/// Cargo features the driver was built with. Only says what the driver can do at all, the device
+/// may still lack support for some of them.
+pub(crate) const FEATURES: i32 = {
+    let mut features = 0;
+    if cfg!(feature = "h264") {
+        features |= FEATURE_H264;
+    }
+    if cfg!(feature = "h265") {
+        features |= FEATURE_H265;
+    }
+    if cfg!(feature = "av1") {
+        features |= FEATURE_AV1;
+    }
+    if cfg!(feature = "vp9") {
+        features |= FEATURE_VP9;
+    }
+    if cfg!(feature = "encode") {
+        features |= FEATURE_ENCODE;
+    }
+    if cfg!(feature = "vpp") {
+        features |= FEATURE_VPP;
+    }
+    features
+};
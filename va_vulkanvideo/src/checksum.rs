@@ -0,0 +1,133 @@
+//! Checksums of decoded pictures, enabled with `VAVK_CHECKSUM=1`.
+//!
+//! When output is corrupted, the first question is whether the decoded pictures are already wrong
+//! or only get broken further down the pipeline (VPP, export, the compositor). With this enabled,
+//! a CRC-32 of the visible area of each decoded surface is logged, which can be compared against
+//! the per-frame checksums of a reference decoder (e.g. `ffmpeg -f framecrc`, after converting it
+//! to the same plane layout).
+
+// Not wired up until pictures are decoded.
+#![allow(dead_code)]
+
+use std::sync::LazyLock;
+
+use log::info;
+use va_backend_sys::VASurfaceID;
+
+/// Environment variable enabling the checksums.
+const CHECKSUM_ENV: &str = "VAVK_CHECKSUM";
+
+static ENABLED: LazyLock<bool> =
+    LazyLock::new(|| std::env::var_os(CHECKSUM_ENV).is_some_and(|value| value == "1"));
+
+/// Whether decoded pictures should be read back and checksummed.
+pub(crate) fn enabled() -> bool {
+    *ENABLED
+}
+
+/// CRC-32 (IEEE 802.3) lookup table.
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Running CRC-32 over the rows of all planes of a picture.
+#[derive(Debug)]
+pub(crate) struct PictureChecksum {
+    crc: u32,
+}
+
+impl PictureChecksum {
+    pub(crate) fn new() -> Self {
+        Self { crc: !0 }
+    }
+
+    /// Adds the first `row_len` bytes of each of the `rows` rows of a plane starting at `data`,
+    /// `pitch` bytes apart. Padding beyond the visible area is skipped, since its contents are
+    /// undefined. A `pitch` of 0 adds nothing.
+    pub(crate) fn add_plane(&mut self, data: &[u8], pitch: usize, row_len: usize, rows: usize) {
+        if pitch == 0 {
+            return;
+        }
+        for row in data.chunks(pitch).take(rows) {
+            for &byte in &row[..row_len.min(row.len())] {
+                self.crc =
+                    CRC32_TABLE[((self.crc ^ u32::from(byte)) & 0xff) as usize] ^ (self.crc >> 8);
+            }
+        }
+    }
+
+    pub(crate) fn finish(self) -> u32 {
+        !self.crc
+    }
+}
+
+/// Logs the checksum of the picture decoded into `surface`, the `frame`th of its context.
+pub(crate) fn log(surface: VASurfaceID, frame: u64, checksum: PictureChecksum) {
+    info!(
+        "Frame {frame} (surface {surface:#x}): CRC-32 {:08x}",
+        checksum.finish()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checksum(data: &[u8], pitch: usize, row_len: usize, rows: usize) -> u32 {
+        let mut checksum = PictureChecksum::new();
+        checksum.add_plane(data, pitch, row_len, rows);
+        checksum.finish()
+    }
+
+    /// Check value from the CRC catalogue.
+    #[test]
+    fn check_value() {
+        assert_eq!(checksum(b"123456789", 9, 9, 1), 0xcbf4_3926);
+        assert_eq!(checksum(b"123456789", 3, 3, 3), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn planes_are_concatenated() {
+        let mut planes = PictureChecksum::new();
+        planes.add_plane(b"1234", 4, 4, 1);
+        planes.add_plane(b"56789", 5, 5, 1);
+        assert_eq!(planes.finish(), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn padding_is_skipped() {
+        assert_eq!(checksum(b"123xx456xx789xx", 5, 3, 3), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn rows_limit() {
+        assert_eq!(checksum(b"123456789abc", 3, 3, 3), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn zero_pitch() {
+        assert_eq!(checksum(b"123456789", 0, 9, 1), checksum(b"", 9, 9, 1));
+    }
+
+    #[test]
+    fn short_last_row() {
+        // The last row of a plane doesn't need its padding
+        assert_eq!(checksum(b"123xx456xx789", 5, 3, 3), 0xcbf4_3926);
+    }
+}
@@ -0,0 +1,571 @@
+//! Configs (`VAConfigID`) and the config attributes we support.
+
+use ash::vk;
+use log::{error, warn};
+use va_backend_sys::{VAConfigAttrib, VAConfigAttribType, VAEntrypoint, VAProfile};
+
+use crate::{VaError, VulkanData, supported_entrypoints, surfaces};
+
+/// Driver-specific config attribute types start here, well clear of `VAConfigAttribTypeMax`.
+const CONFIG_ATTRIB_DRIVER_BASE: VAConfigAttribType = 0x1000;
+
+/// How decoded pictures relate to the DPB. The value is a mask of [`DPB_MODE_COINCIDE`] and
+/// [`DPB_MODE_DISTINCT`]. If both are set, the driver picks, preferring coinciding pictures, and
+/// configs report the picked mode.
+pub(crate) const CONFIG_ATTRIB_DPB_MODE: VAConfigAttribType = CONFIG_ATTRIB_DRIVER_BASE;
+/// Decode directly into the DPB and use the DPB pictures as surfaces. Saves a copy per frame.
+pub(crate) const DPB_MODE_COINCIDE: u32 = 0x1;
+/// Decode into separate output pictures. Needed when surfaces are shared with other APIs while
+/// the DPB is still in use.
+pub(crate) const DPB_MODE_DISTINCT: u32 = 0x2;
+
+/// Whether vaGetImage/vaPutImage convert formats on the CPU (1) instead of on the GPU (0, default).
+/// Useful to work around broken compute queues.
+///
+/// Reserved, but not advertised while nothing reads it.
+// TODO: Advertise for VAEntrypointVLD (default 0, values 0 and 1) once vaGetImage/vaPutImage are
+// implemented. A per-config toggle for Vulkan validation isn't possible: the validation layer is
+// enabled on the instance, which is shared by all displays and created before any config exists.
+pub(crate) const CONFIG_ATTRIB_SOFTWARE_CSC: VAConfigAttribType = CONFIG_ATTRIB_DRIVER_BASE + 1;
+
+/// H.264 entropy coding modes the encoder may use, a mask of [`ENTROPY_MODE_CAVLC`] and
+/// [`ENTROPY_MODE_CABAC`]. Pictures whose `entropy_coding_mode_flag` isn't allowed have to be
+/// rejected by vaRenderPicture, instead of producing broken bitstreams.
+pub(crate) const CONFIG_ATTRIB_ENTROPY_MODES: VAConfigAttribType = CONFIG_ATTRIB_DRIVER_BASE + 2;
+/// `entropy_coding_mode_flag` = 0
+pub(crate) const ENTROPY_MODE_CAVLC: u32 = 0x1;
+/// `entropy_coding_mode_flag` = 1
+pub(crate) const ENTROPY_MODE_CABAC: u32 = 0x2;
+
+// TODO: Opt-in driver attribute letting contexts created from the same config share their
+// VkVideoSessionParametersKHR and capability probes, for tiled/parallel decoders that split one
+// stream across several contexts.
+
+// TODO: Driver attribute for simulcast encode contexts, giving the scale factors of additional
+// downscaled layers (e.g. 1/2 and 1/4). Each picture would be scaled on the compute queue and
+// every layer encoded in its own session, with the coded data of each layer in a separate
+// segment of the coded buffer, saving conferencing clients a VPP round trip per layer.
+
+/// Config attributes we know about, in the order vaQueryConfigAttributes returns them.
+pub(crate) const CONFIG_ATTRIBS: [VAConfigAttribType; 10] = [
+    va_backend_sys::VAConfigAttribType_VAConfigAttribRTFormat,
+    va_backend_sys::VAConfigAttribType_VAConfigAttribRateControl,
+    va_backend_sys::VAConfigAttribType_VAConfigAttribDecSliceMode,
+    va_backend_sys::VAConfigAttribType_VAConfigAttribEncInterlaced,
+    va_backend_sys::VAConfigAttribType_VAConfigAttribEncMaxRefFrames,
+    va_backend_sys::VAConfigAttribType_VAConfigAttribEncMaxSlices,
+    va_backend_sys::VAConfigAttribType_VAConfigAttribMaxPictureWidth,
+    va_backend_sys::VAConfigAttribType_VAConfigAttribMaxPictureHeight,
+    CONFIG_ATTRIB_DPB_MODE,
+    CONFIG_ATTRIB_ENTROPY_MODES,
+];
+
+/// Each attribute must only be listed once, vaMaxNumConfigAttributes is derived from the length.
+const _: () = {
+    let mut i = 0;
+    while i < CONFIG_ATTRIBS.len() {
+        let mut j = i + 1;
+        while j < CONFIG_ATTRIBS.len() {
+            assert!(CONFIG_ATTRIBS[i] != CONFIG_ATTRIBS[j]);
+            j += 1;
+        }
+        i += 1;
+    }
+};
+
+/// Render target format of the pictures of a VA profile.
+fn rt_format(profile: VAProfile) -> u32 {
+    match profile {
+        va_backend_sys::VAProfile_VAProfileHEVCMain10 => va_backend_sys::VA_RT_FORMAT_YUV420_10,
+        // AV1 High is the 4:4:4 profile
+        va_backend_sys::VAProfile_VAProfileAV1Profile1 => va_backend_sys::VA_RT_FORMAT_YUV444,
+        _ => va_backend_sys::VA_RT_FORMAT_YUV420,
+    }
+}
+
+/// Checks that `entrypoint` is supported for `profile`.
+pub(crate) fn check_profile_entrypoint(
+    vulkan: &VulkanData,
+    profile: VAProfile,
+    entrypoint: VAEntrypoint,
+) -> Result<(), VaError> {
+    if supported_entrypoints(vulkan, profile)?.contains(&entrypoint) {
+        Ok(())
+    } else {
+        Err(VaError::UnsupportedEntrypoint)
+    }
+}
+
+/// Value of the config attribute `attrib_type` for `profile`/`entrypoint`, or `None` if it isn't
+/// supported. For bitmask attributes, this is the mask of all supported values.
+pub(crate) fn supported_value(
+    vulkan: &VulkanData,
+    profile: VAProfile,
+    entrypoint: VAEntrypoint,
+    attrib_type: VAConfigAttribType,
+) -> Option<u32> {
+    let decode = entrypoint == va_backend_sys::VAEntrypoint_VAEntrypointVLD;
+    let encode = entrypoint == va_backend_sys::VAEntrypoint_VAEntrypointEncSlice;
+    match attrib_type {
+        // Decode can only output what the implementation supports for the profile
+        va_backend_sys::VAConfigAttribType_VAConfigAttribRTFormat if decode => vulkan
+            .decode_capabilities(profile)
+            .and_then(|capabilities| surfaces::rt_format(capabilities.output_fourcc)),
+        va_backend_sys::VAConfigAttribType_VAConfigAttribRTFormat => Some(rt_format(profile)),
+        // Other modes (VBR_CONSTRAINED, QVBR, ICQ, ...) need rate control in the driver, the
+        // implementation default mode has no VA equivalent
+        va_backend_sys::VAConfigAttribType_VAConfigAttribRateControl if encode => {
+            let flags = vulkan.encode_capabilities(profile)?.rate_control_modes;
+            let mut modes = 0;
+            if flags.contains(vk::VideoEncodeRateControlModeFlagsKHR::DISABLED) {
+                modes |= va_backend_sys::VA_RC_CQP;
+            }
+            if flags.contains(vk::VideoEncodeRateControlModeFlagsKHR::CBR) {
+                modes |= va_backend_sys::VA_RC_CBR;
+            }
+            if flags.contains(vk::VideoEncodeRateControlModeFlagsKHR::VBR) {
+                modes |= va_backend_sys::VA_RC_VBR;
+            }
+            (modes != 0).then_some(modes)
+        }
+        // Slices are collected across vaRenderPicture calls and the whole picture is decoded in
+        // vaEndPicture. The base (short format) mode would need us to find slices in the
+        // bitstream ourselves.
+        va_backend_sys::VAConfigAttribType_VAConfigAttribDecSliceMode if decode => {
+            Some(va_backend_sys::VA_DEC_SLICE_MODE_NORMAL)
+        }
+        // Vulkan has no field or MBAFF encoding
+        // TODO: Interlaced encode for broadcast contribution. H.264 needs field_pic_flag/MBAFF in
+        // the Vulkan std encode structures, which don't have them yet. HEVC codes fields as
+        // separate pictures, so it could be done today: split each surface (or take separate
+        // field surfaces) into half-height pictures, set field_seq_flag in the VUI and emit pic
+        // timing SEI with the pic_struct, then advertise VA_ENC_INTERLACED_FIELD for HEVC.
+        va_backend_sys::VAConfigAttribType_VAConfigAttribEncInterlaced if encode => {
+            Some(va_backend_sys::VA_ENC_INTERLACED_NONE)
+        }
+        // Encoders size their GOP structure from this: list 0 in the low 16 bits, list 1 in the
+        // high 16 bits. A list 1 limit of 0 means no B-frames with backward references.
+        va_backend_sys::VAConfigAttribType_VAConfigAttribEncMaxRefFrames if encode => {
+            let capabilities = vulkan.encode_capabilities(profile)?;
+            Some(
+                capabilities.max_l0_references.min(0xffff)
+                    | capabilities.max_l1_references.min(0xffff) << 16,
+            )
+        }
+        va_backend_sys::VAConfigAttribType_VAConfigAttribEncMaxSlices if encode => vulkan
+            .encode_capabilities(profile)
+            .map(|capabilities| capabilities.max_slices),
+        va_backend_sys::VAConfigAttribType_VAConfigAttribMaxPictureWidth if decode => vulkan
+            .decode_capabilities(profile)
+            .map(|capabilities| capabilities.max_coded_extent.width),
+        va_backend_sys::VAConfigAttribType_VAConfigAttribMaxPictureHeight if decode => vulkan
+            .decode_capabilities(profile)
+            .map(|capabilities| capabilities.max_coded_extent.height),
+        va_backend_sys::VAConfigAttribType_VAConfigAttribMaxPictureWidth if encode => vulkan
+            .encode_capabilities(profile)
+            .map(|capabilities| capabilities.max_coded_extent.width),
+        va_backend_sys::VAConfigAttribType_VAConfigAttribMaxPictureHeight if encode => vulkan
+            .encode_capabilities(profile)
+            .map(|capabilities| capabilities.max_coded_extent.height),
+        CONFIG_ATTRIB_DPB_MODE if decode => {
+            let flags = vulkan.decode_capabilities(profile)?.decode_flags;
+            let mut modes = 0;
+            if flags.contains(vk::VideoDecodeCapabilityFlagsKHR::DPB_AND_OUTPUT_COINCIDE) {
+                modes |= DPB_MODE_COINCIDE;
+            }
+            if flags.contains(vk::VideoDecodeCapabilityFlagsKHR::DPB_AND_OUTPUT_DISTINCT) {
+                modes |= DPB_MODE_DISTINCT;
+            }
+            if vulkan.workarounds.distinct_dpb && modes & DPB_MODE_DISTINCT != 0 {
+                modes &= !DPB_MODE_COINCIDE;
+            }
+            (modes != 0).then_some(modes)
+        }
+        CONFIG_ATTRIB_ENTROPY_MODES if encode => {
+            let flags = vulkan.encode_capabilities(profile)?.h264_std_flags?;
+            let mut modes = 0;
+            if flags.contains(vk::VideoEncodeH264StdFlagsKHR::ENTROPY_CODING_MODE_FLAG_UNSET) {
+                modes |= ENTROPY_MODE_CAVLC;
+            }
+            // CABAC isn't allowed in (Constrained) Baseline
+            let baseline = matches!(
+                profile,
+                va_backend_sys::VAProfile_VAProfileH264Baseline
+                    | va_backend_sys::VAProfile_VAProfileH264ConstrainedBaseline
+            );
+            if flags.contains(vk::VideoEncodeH264StdFlagsKHR::ENTROPY_CODING_MODE_FLAG_SET)
+                && !baseline
+            {
+                modes |= ENTROPY_MODE_CABAC;
+            }
+            (modes != 0).then_some(modes)
+        }
+        _ => None,
+    }
+}
+
+/// Lowest bit of `mask`, for attributes where a config only has a single mode.
+fn single_mode(mask: u32) -> u32 {
+    mask & mask.wrapping_neg()
+}
+
+/// Value a config gets for a supported attribute the client didn't set.
+fn default_value(attrib_type: VAConfigAttribType, supported: u32) -> u32 {
+    match attrib_type {
+        // CBR if available
+        va_backend_sys::VAConfigAttribType_VAConfigAttribRateControl => single_mode(supported),
+        CONFIG_ATTRIB_DPB_MODE => single_mode(supported),
+        _ => supported,
+    }
+}
+
+/// Effective value of an attribute the client set to `requested`. Bits we don't support are
+/// dropped, and modes are narrowed down to the one the config will actually use, so that
+/// vaQueryConfigAttributes tells the client what it got. Fails if nothing usable is left.
+fn negotiate(
+    attrib_type: VAConfigAttribType,
+    requested: u32,
+    supported: u32,
+) -> Result<u32, VaError> {
+    let attainable = requested & supported;
+    match attrib_type {
+        va_backend_sys::VAConfigAttribType_VAConfigAttribRTFormat => {
+            if attainable == 0 {
+                error!("Unsupported RT format {requested:#x}");
+                return Err(VaError::UnsupportedRtformat);
+            }
+            Ok(attainable)
+        }
+        va_backend_sys::VAConfigAttribType_VAConfigAttribRateControl => {
+            if attainable != 0 {
+                return Ok(single_mode(attainable));
+            }
+            // The bitrate modes take the same parameters, so one can stand in for the other.
+            // Constant QP can't be emulated without losing what the client asked for.
+            let fallbacks: &[u32] = match requested {
+                va_backend_sys::VA_RC_CBR => &[va_backend_sys::VA_RC_VBR],
+                va_backend_sys::VA_RC_VBR => &[va_backend_sys::VA_RC_CBR],
+                va_backend_sys::VA_RC_VBR_CONSTRAINED => {
+                    &[va_backend_sys::VA_RC_VBR, va_backend_sys::VA_RC_CBR]
+                }
+                _ => &[],
+            };
+            fallbacks
+                .iter()
+                .copied()
+                .find(|&mode| supported & mode != 0)
+                .ok_or_else(|| {
+                    error!("Unsupported rate control mode {requested:#x}");
+                    VaError::InvalidValue
+                })
+        }
+        va_backend_sys::VAConfigAttribType_VAConfigAttribDecSliceMode => {
+            if attainable == 0 {
+                error!("Unsupported slice decoding mode {requested:#x}");
+                return Err(VaError::InvalidValue);
+            }
+            Ok(attainable)
+        }
+        va_backend_sys::VAConfigAttribType_VAConfigAttribEncInterlaced => {
+            // VA_ENC_INTERLACED_NONE is 0, so this is the only value that can't be narrowed
+            if requested & !supported != 0 {
+                error!("Unsupported interlaced encoding {requested:#x}");
+                return Err(VaError::InvalidValue);
+            }
+            Ok(requested)
+        }
+        CONFIG_ATTRIB_DPB_MODE => {
+            if attainable == 0 {
+                error!("Unsupported DPB mode {requested:#x}");
+                return Err(VaError::InvalidValue);
+            }
+            Ok(single_mode(attainable))
+        }
+        CONFIG_ATTRIB_ENTROPY_MODES => {
+            if attainable == 0 {
+                error!("Unsupported entropy coding modes {requested:#x}");
+                return Err(VaError::InvalidValue);
+            }
+            Ok(attainable)
+        }
+        // Limits (picture size, references, slices) are ours to report, not the client's to set
+        _ => Ok(supported),
+    }
+}
+
+pub(crate) struct Config {
+    pub(crate) profile: VAProfile,
+    pub(crate) entrypoint: VAEntrypoint,
+    /// Values of all supported attributes, with the ones requested by the client negotiated. This
+    /// is what vaQueryConfigAttributes returns.
+    pub(crate) attribs: Vec<VAConfigAttrib>,
+}
+
+impl Config {
+    // TODO: WebRTC preset for H.264 encode (Constrained Baseline, or selected through
+    // VAConfigAttribEncQualityRange/the quality level): a single reference, no B-frames, intra
+    // refresh, and VkVideoEncodeUsageInfoKHR with CONFERENCING usage and the ULTRA_LOW_LATENCY
+    // tuning mode, matching what Chromium requests.
+    pub(crate) fn new(
+        vulkan: &VulkanData,
+        profile: VAProfile,
+        entrypoint: VAEntrypoint,
+        requested: &[VAConfigAttrib],
+    ) -> Result<Self, VaError> {
+        check_profile_entrypoint(vulkan, profile, entrypoint)?;
+
+        let mut attribs = CONFIG_ATTRIBS
+            .into_iter()
+            .filter_map(|type_| {
+                supported_value(vulkan, profile, entrypoint, type_).map(|supported| {
+                    let value = default_value(type_, supported);
+                    VAConfigAttrib { type_, value }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for request in requested {
+            let Some(attrib) = attribs.iter_mut().find(|a| a.type_ == request.type_) else {
+                error!("Config attribute {} is not supported", request.type_);
+                return Err(VaError::AttrNotSupported);
+            };
+            let supported = supported_value(vulkan, profile, entrypoint, request.type_)
+                .expect("attribs only contains supported attributes");
+
+            attrib.value = negotiate(request.type_, request.value, supported)?;
+            if attrib.value != request.value {
+                warn!(
+                    "Config attribute {} adjusted from {:#x} to {:#x}",
+                    request.type_, request.value, attrib.value
+                );
+            }
+        }
+
+        Ok(Self {
+            profile,
+            entrypoint,
+            attribs,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use va_backend_sys::{
+        VA_ENC_INTERLACED_FIELD, VA_ENC_INTERLACED_NONE, VA_RC_CBR, VA_RC_CQP, VA_RC_VBR,
+        VA_RC_VBR_CONSTRAINED, VAStatus,
+    };
+
+    use super::*;
+
+    fn status(result: Result<u32, VaError>) -> Result<u32, VAStatus> {
+        result.map_err(VAStatus::from)
+    }
+
+    fn invalid_value() -> Result<u32, VAStatus> {
+        Err(VaError::InvalidValue.into())
+    }
+
+    const RATE_CONTROL: VAConfigAttribType =
+        va_backend_sys::VAConfigAttribType_VAConfigAttribRateControl;
+
+    #[test]
+    fn default_values() {
+        let both = DPB_MODE_COINCIDE | DPB_MODE_DISTINCT;
+        assert_eq!(
+            default_value(CONFIG_ATTRIB_DPB_MODE, both),
+            DPB_MODE_COINCIDE
+        );
+        assert_eq!(
+            default_value(RATE_CONTROL, VA_RC_CBR | VA_RC_VBR),
+            VA_RC_CBR
+        );
+        let entropy = ENTROPY_MODE_CAVLC | ENTROPY_MODE_CABAC;
+        assert_eq!(default_value(CONFIG_ATTRIB_ENTROPY_MODES, entropy), entropy);
+    }
+
+    #[test]
+    fn rt_format() {
+        let attrib = va_backend_sys::VAConfigAttribType_VAConfigAttribRTFormat;
+        let supported = va_backend_sys::VA_RT_FORMAT_YUV420;
+        let requested = supported | va_backend_sys::VA_RT_FORMAT_YUV420_10;
+        assert_eq!(
+            status(negotiate(attrib, requested, supported)),
+            Ok(supported)
+        );
+        assert_eq!(
+            status(negotiate(
+                attrib,
+                va_backend_sys::VA_RT_FORMAT_YUV444,
+                supported
+            )),
+            Err(VaError::UnsupportedRtformat.into())
+        );
+    }
+
+    #[test]
+    fn rate_control_narrowed_to_one_mode() {
+        let supported = VA_RC_CQP | VA_RC_CBR | VA_RC_VBR;
+        let requested = VA_RC_CBR | VA_RC_VBR;
+        assert_eq!(
+            status(negotiate(RATE_CONTROL, requested, supported)),
+            Ok(VA_RC_CBR)
+        );
+        assert_eq!(
+            status(negotiate(RATE_CONTROL, VA_RC_VBR, supported)),
+            Ok(VA_RC_VBR)
+        );
+    }
+
+    #[test]
+    fn rate_control_fallbacks() {
+        assert_eq!(
+            status(negotiate(RATE_CONTROL, VA_RC_CBR, VA_RC_VBR)),
+            Ok(VA_RC_VBR)
+        );
+        assert_eq!(
+            status(negotiate(RATE_CONTROL, VA_RC_VBR, VA_RC_CBR)),
+            Ok(VA_RC_CBR)
+        );
+        assert_eq!(
+            status(negotiate(
+                RATE_CONTROL,
+                VA_RC_VBR_CONSTRAINED,
+                VA_RC_CBR | VA_RC_VBR
+            )),
+            Ok(VA_RC_VBR)
+        );
+        assert_eq!(
+            status(negotiate(RATE_CONTROL, VA_RC_VBR_CONSTRAINED, VA_RC_CBR)),
+            Ok(VA_RC_CBR)
+        );
+    }
+
+    #[test]
+    fn rate_control_without_fallback() {
+        // Constant QP can't stand in for a bitrate mode, nor the other way around
+        assert_eq!(
+            status(negotiate(RATE_CONTROL, VA_RC_CBR, VA_RC_CQP)),
+            invalid_value()
+        );
+        assert_eq!(
+            status(negotiate(RATE_CONTROL, VA_RC_CQP, VA_RC_CBR | VA_RC_VBR)),
+            invalid_value()
+        );
+        assert_eq!(
+            status(negotiate(RATE_CONTROL, VA_RC_VBR_CONSTRAINED, VA_RC_CQP)),
+            invalid_value()
+        );
+        // Fallbacks only apply to a single requested mode
+        assert_eq!(
+            status(negotiate(RATE_CONTROL, VA_RC_CBR | VA_RC_CQP, VA_RC_VBR)),
+            invalid_value()
+        );
+    }
+
+    #[test]
+    fn dec_slice_mode() {
+        let attrib = va_backend_sys::VAConfigAttribType_VAConfigAttribDecSliceMode;
+        let supported = va_backend_sys::VA_DEC_SLICE_MODE_NORMAL;
+        assert_eq!(
+            status(negotiate(attrib, supported, supported)),
+            Ok(supported)
+        );
+        assert_eq!(
+            status(negotiate(
+                attrib,
+                va_backend_sys::VA_DEC_SLICE_MODE_BASE,
+                supported
+            )),
+            invalid_value()
+        );
+    }
+
+    #[test]
+    fn enc_interlaced() {
+        let attrib = va_backend_sys::VAConfigAttribType_VAConfigAttribEncInterlaced;
+        assert_eq!(
+            status(negotiate(
+                attrib,
+                VA_ENC_INTERLACED_NONE,
+                VA_ENC_INTERLACED_NONE
+            )),
+            Ok(VA_ENC_INTERLACED_NONE)
+        );
+        assert_eq!(
+            status(negotiate(
+                attrib,
+                VA_ENC_INTERLACED_FIELD,
+                VA_ENC_INTERLACED_NONE
+            )),
+            invalid_value()
+        );
+        // Requesting a supported value isn't narrowed
+        assert_eq!(
+            status(negotiate(
+                attrib,
+                VA_ENC_INTERLACED_FIELD,
+                VA_ENC_INTERLACED_FIELD
+            )),
+            Ok(VA_ENC_INTERLACED_FIELD)
+        );
+    }
+
+    #[test]
+    fn dpb_mode() {
+        let both = DPB_MODE_COINCIDE | DPB_MODE_DISTINCT;
+        assert_eq!(
+            status(negotiate(CONFIG_ATTRIB_DPB_MODE, both, both)),
+            Ok(DPB_MODE_COINCIDE)
+        );
+        assert_eq!(
+            status(negotiate(CONFIG_ATTRIB_DPB_MODE, both, DPB_MODE_DISTINCT)),
+            Ok(DPB_MODE_DISTINCT)
+        );
+        assert_eq!(
+            status(negotiate(
+                CONFIG_ATTRIB_DPB_MODE,
+                DPB_MODE_DISTINCT,
+                DPB_MODE_COINCIDE
+            )),
+            invalid_value()
+        );
+    }
+
+    #[test]
+    fn entropy_modes() {
+        let both = ENTROPY_MODE_CAVLC | ENTROPY_MODE_CABAC;
+        assert_eq!(
+            status(negotiate(
+                CONFIG_ATTRIB_ENTROPY_MODES,
+                both,
+                ENTROPY_MODE_CAVLC
+            )),
+            Ok(ENTROPY_MODE_CAVLC)
+        );
+        assert_eq!(
+            status(negotiate(CONFIG_ATTRIB_ENTROPY_MODES, both, both)),
+            Ok(both)
+        );
+        assert_eq!(
+            status(negotiate(
+                CONFIG_ATTRIB_ENTROPY_MODES,
+                ENTROPY_MODE_CABAC,
+                ENTROPY_MODE_CAVLC
+            )),
+            invalid_value()
+        );
+    }
+
+    #[test]
+    fn limits_are_reported_as_supported() {
+        for attrib in [
+            va_backend_sys::VAConfigAttribType_VAConfigAttribMaxPictureWidth,
+            va_backend_sys::VAConfigAttribType_VAConfigAttribEncMaxRefFrames,
+            va_backend_sys::VAConfigAttribType_VAConfigAttribEncMaxSlices,
+        ] {
+            assert_eq!(status(negotiate(attrib, 1234, 4096)), Ok(4096));
+            assert_eq!(status(negotiate(attrib, 0, 4096)), Ok(4096));
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! The error type of the driver functions.
+//!
+//! Clients only ever see a `VAStatus`, and libva at most logs its name. To make failures
+//! debuggable, errors carry the Vulkan result they originate from, the VA object involved and a
+//! message, and are logged once when they cross the FFI boundary.
+
+use std::{borrow::Cow, fmt, panic::Location, str::FromStr, sync::LazyLock};
+
+use ash::vk;
+use log::{Level, LevelFilter, log, warn};
+use va_backend_sys::VAStatus;
+
+/// Environment variable setting the level errors are logged at when returned to the client
+/// (`off`, `error`, `warn`, `info`, `debug` or `trace`). Defaults to `warn`.
+const ERROR_LOG_LEVEL_ENV: &str = "VAVK_ERROR_LOG_LEVEL";
+
+static ERROR_LOG_LEVEL: LazyLock<Option<Level>> = LazyLock::new(|| {
+    let Ok(value) = std::env::var(ERROR_LOG_LEVEL_ENV) else {
+        return Some(Level::Warn);
+    };
+    match LevelFilter::from_str(value.trim()) {
+        Ok(filter) => filter.to_level(),
+        Err(_) => {
+            warn!("Ignoring invalid {ERROR_LOG_LEVEL_ENV}={value:?}");
+            Some(Level::Warn)
+        }
+    }
+});
+
+#[derive(Debug, Clone)]
+pub(crate) struct VaError {
+    /// Status returned to the client.
+    status: VAStatus,
+    /// Name of `status`, for logging.
+    name: &'static str,
+    /// The Vulkan error this originates from.
+    vk_result: Option<vk::Result>,
+    /// ID of the VA object (config, surface, ...) involved.
+    object_id: Option<u32>,
+    message: Option<Cow<'static, str>>,
+}
+
+macro_rules! va_errors {
+    ($($name:ident = $status:ident,)*) => {
+        // Named like the enum variants they replace, so errors read the same at the call sites.
+        #[allow(non_upper_case_globals, dead_code)]
+        impl VaError {
+            $(
+                pub(crate) const $name: Self = Self {
+                    status: va_backend_sys::$status as VAStatus,
+                    name: stringify!($name),
+                    vk_result: None,
+                    object_id: None,
+                    message: None,
+                };
+            )*
+        }
+    };
+}
+
+va_errors! {
+    OperationFailed = VA_STATUS_ERROR_OPERATION_FAILED,
+    AllocationFailed = VA_STATUS_ERROR_ALLOCATION_FAILED,
+    InvalidDisplay = VA_STATUS_ERROR_INVALID_DISPLAY,
+    InvalidConfig = VA_STATUS_ERROR_INVALID_CONFIG,
+    InvalidContext = VA_STATUS_ERROR_INVALID_CONTEXT,
+    InvalidSurface = VA_STATUS_ERROR_INVALID_SURFACE,
+    InvalidBuffer = VA_STATUS_ERROR_INVALID_BUFFER,
+    InvalidImage = VA_STATUS_ERROR_INVALID_IMAGE,
+    InvalidSubpicture = VA_STATUS_ERROR_INVALID_SUBPICTURE,
+    AttrNotSupported = VA_STATUS_ERROR_ATTR_NOT_SUPPORTED,
+    MaxNumExceeded = VA_STATUS_ERROR_MAX_NUM_EXCEEDED,
+    UnsupportedProfile = VA_STATUS_ERROR_UNSUPPORTED_PROFILE,
+    UnsupportedEntrypoint = VA_STATUS_ERROR_UNSUPPORTED_ENTRYPOINT,
+    UnsupportedRtformat = VA_STATUS_ERROR_UNSUPPORTED_RT_FORMAT,
+    UnsupportedBuffertype = VA_STATUS_ERROR_UNSUPPORTED_BUFFERTYPE,
+    SurfaceBusy = VA_STATUS_ERROR_SURFACE_BUSY,
+    FlagNotSupported = VA_STATUS_ERROR_FLAG_NOT_SUPPORTED,
+    InvalidParameter = VA_STATUS_ERROR_INVALID_PARAMETER,
+    ResolutionNotSupported = VA_STATUS_ERROR_RESOLUTION_NOT_SUPPORTED,
+    Unimplemented = VA_STATUS_ERROR_UNIMPLEMENTED,
+    SurfaceInDisplaying = VA_STATUS_ERROR_SURFACE_IN_DISPLAYING,
+    InvalidImageFormat = VA_STATUS_ERROR_INVALID_IMAGE_FORMAT,
+    DecodingError = VA_STATUS_ERROR_DECODING_ERROR,
+    EncodingError = VA_STATUS_ERROR_ENCODING_ERROR,
+    InvalidValue = VA_STATUS_ERROR_INVALID_VALUE,
+    UnsupportedMemoryType = VA_STATUS_ERROR_UNSUPPORTED_MEMORY_TYPE,
+}
+
+impl VaError {
+    /// Attaches the ID of the VA object involved.
+    pub(crate) fn object(mut self, id: u32) -> Self {
+        self.object_id = Some(id);
+        self
+    }
+
+    /// Attaches a message describing what failed.
+    pub(crate) fn context(mut self, message: impl Into<Cow<'static, str>>) -> Self {
+        self.message = Some(message.into());
+        self
+    }
+
+    /// Logs the error as returned from the driver function at `location`, at the level set by
+    /// [`ERROR_LOG_LEVEL_ENV`].
+    pub(crate) fn log_returned(&self, location: &Location) {
+        if let Some(level) = *ERROR_LOG_LEVEL {
+            log!(level, "Returning {self} from {location}");
+        }
+    }
+}
+
+/// Maps Vulkan errors to the closest VA status, keeping the Vulkan result for the log.
+impl From<vk::Result> for VaError {
+    fn from(result: vk::Result) -> Self {
+        let err = match result {
+            vk::Result::ERROR_OUT_OF_HOST_MEMORY | vk::Result::ERROR_OUT_OF_DEVICE_MEMORY => {
+                VaError::AllocationFailed
+            }
+            _ => VaError::OperationFailed,
+        };
+        Self {
+            vk_result: Some(result),
+            ..err
+        }
+    }
+}
+
+impl From<VaError> for VAStatus {
+    fn from(err: VaError) -> Self {
+        err.status
+    }
+}
+
+impl fmt::Display for VaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name)?;
+        if let Some(message) = &self.message {
+            write!(f, ": {message}")?;
+        }
+        if let Some(id) = self.object_id {
+            write!(f, " (object {id:#x})")?;
+        }
+        if let Some(result) = self.vk_result {
+            write!(f, " (Vulkan: {result})")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for VaError {}
@@ -0,0 +1,309 @@
+//! Exporting surfaces as dma-bufs through vaExportSurfaceHandle.
+//!
+//! Consumers disagree on how planes should be described: GStreamer and most EGL importers want a
+//! single layer with all planes of the format (e.g. `DRM_FORMAT_NV12`), while some Wayland
+//! clients import each plane as its own single-plane layer (`R8` + `GR88`). Both are supported,
+//! selected by `VA_EXPORT_SURFACE_COMPOSED_LAYERS`/`VA_EXPORT_SURFACE_SEPARATE_LAYERS`.
+//!
+//! The access flags tell us what the importer will do with the image. Exported images are
+//! released to `VK_QUEUE_FAMILY_EXTERNAL` in the `GENERAL` layout. Read-only (display) exports
+//! leave the contents alone, so exporting the same surface again needs no further barriers.
+//! Writable exports are assumed to have modified the image once we use it again, so the next use
+//! has to acquire it from the external queue family instead of discarding its contents.
+
+use ash::vk;
+use va_backend_sys::VADRMPRIMESurfaceDescriptor;
+
+use crate::{VaError, barriers::ImageUse};
+
+const fn drm_fourcc(code: &[u8; 4]) -> u32 {
+    u32::from_le_bytes(*code)
+}
+
+const DRM_FORMAT_R8: u32 = drm_fourcc(b"R8  ");
+const DRM_FORMAT_R16: u32 = drm_fourcc(b"R16 ");
+const DRM_FORMAT_GR88: u32 = drm_fourcc(b"GR88");
+const DRM_FORMAT_GR1616: u32 = drm_fourcc(b"GR32");
+const DRM_FORMAT_NV12: u32 = drm_fourcc(b"NV12");
+const DRM_FORMAT_P010: u32 = drm_fourcc(b"P010");
+
+/// How the planes of an exported surface are grouped into layers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum ExportLayers {
+    /// One layer per plane.
+    Separate,
+    /// A single layer with all planes.
+    Composed,
+}
+
+impl ExportLayers {
+    /// Parses the layer flags of vaExportSurfaceHandle, exactly one of which must be set.
+    pub(crate) fn from_flags(flags: u32) -> Result<Self, VaError> {
+        let separate = flags & va_backend_sys::VA_EXPORT_SURFACE_SEPARATE_LAYERS != 0;
+        let composed = flags & va_backend_sys::VA_EXPORT_SURFACE_COMPOSED_LAYERS != 0;
+        match (separate, composed) {
+            (true, false) => Ok(Self::Separate),
+            (false, true) => Ok(Self::Composed),
+            _ => Err(VaError::InvalidParameter.context(format!(
+                "export flags {flags:#x} need exactly one layer mode"
+            ))),
+        }
+    }
+}
+
+/// What the importer of an exported surface may do with it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct ExportAccess {
+    pub(crate) read: bool,
+    pub(crate) write: bool,
+}
+
+impl ExportAccess {
+    /// Parses the access flags of vaExportSurfaceHandle, at least one of which must be set.
+    pub(crate) fn from_flags(flags: u32) -> Result<Self, VaError> {
+        let access = Self {
+            read: flags & va_backend_sys::VA_EXPORT_SURFACE_READ_ONLY != 0,
+            write: flags & va_backend_sys::VA_EXPORT_SURFACE_WRITE_ONLY != 0,
+        };
+        if !access.read && !access.write {
+            return Err(VaError::InvalidParameter
+                .context(format!("export flags {flags:#x} don't allow any access")));
+        }
+        Ok(access)
+    }
+
+    /// Use of the image by the importer, to be passed to [`crate::barriers::ImageTracker`] at
+    /// export time. Only the release half of the resulting ownership transfer is recorded, the
+    /// importer does the acquire.
+    #[allow(dead_code)] // Used once surfaces can be exported
+    pub(crate) fn external_use(self) -> ImageUse {
+        let mut access = vk::AccessFlags2::NONE;
+        if self.read {
+            access |= vk::AccessFlags2::MEMORY_READ;
+        }
+        if self.write {
+            access |= vk::AccessFlags2::MEMORY_WRITE;
+        }
+        ImageUse {
+            queue_family: vk::QUEUE_FAMILY_EXTERNAL,
+            layout: vk::ImageLayout::GENERAL,
+            stage: vk::PipelineStageFlags2::ALL_COMMANDS,
+            access,
+        }
+    }
+}
+
+/// DRM format of the composed layer, and of each separate layer, of a surface with `va_fourcc`.
+fn drm_formats(va_fourcc: u32) -> Option<(u32, &'static [u32])> {
+    match va_fourcc {
+        va_backend_sys::VA_FOURCC_NV12 => {
+            Some((DRM_FORMAT_NV12, &[DRM_FORMAT_R8, DRM_FORMAT_GR88]))
+        }
+        va_backend_sys::VA_FOURCC_P010 => {
+            Some((DRM_FORMAT_P010, &[DRM_FORMAT_R16, DRM_FORMAT_GR1616]))
+        }
+        _ => None,
+    }
+}
+
+/// Plane of an exported image within its (single) dma-buf.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct ExportPlane {
+    pub(crate) offset: u32,
+    pub(crate) pitch: u32,
+}
+
+/// A surface image exported as one dma-buf.
+#[derive(Debug)]
+pub(crate) struct ExportedImage<'a> {
+    pub(crate) va_fourcc: u32,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) fd: i32,
+    pub(crate) size: u32,
+    pub(crate) drm_format_modifier: u64,
+    pub(crate) planes: &'a [ExportPlane],
+}
+
+impl ExportedImage<'_> {
+    /// Describes the image in `descriptor`, grouping the planes according to `layers`.
+    #[allow(dead_code)] // Used once surfaces can be exported
+    pub(crate) fn describe(
+        &self,
+        layers: ExportLayers,
+        descriptor: &mut VADRMPRIMESurfaceDescriptor,
+    ) -> Result<(), VaError> {
+        let Some((composed_format, plane_formats)) = drm_formats(self.va_fourcc) else {
+            return Err(VaError::InvalidImageFormat
+                .context(format!("can't export fourcc {:#x}", self.va_fourcc)));
+        };
+        if plane_formats.len() != self.planes.len() {
+            return Err(VaError::InvalidImageFormat.context(format!(
+                "fourcc {:#x} has {} planes, but the image has {}",
+                self.va_fourcc,
+                plane_formats.len(),
+                self.planes.len()
+            )));
+        }
+
+        descriptor.fourcc = self.va_fourcc;
+        descriptor.width = self.width;
+        descriptor.height = self.height;
+        descriptor.num_objects = 1;
+        descriptor.objects[0].fd = self.fd;
+        descriptor.objects[0].size = self.size;
+        descriptor.objects[0].drm_format_modifier = self.drm_format_modifier;
+
+        match layers {
+            ExportLayers::Composed => {
+                descriptor.num_layers = 1;
+                let layer = &mut descriptor.layers[0];
+                layer.drm_format = composed_format;
+                layer.num_planes = self.planes.len() as u32;
+                for (i, plane) in self.planes.iter().enumerate() {
+                    layer.object_index[i] = 0;
+                    layer.offset[i] = plane.offset;
+                    layer.pitch[i] = plane.pitch;
+                }
+            }
+            ExportLayers::Separate => {
+                descriptor.num_layers = self.planes.len() as u32;
+                for ((layer, plane), &drm_format) in descriptor
+                    .layers
+                    .iter_mut()
+                    .zip(self.planes)
+                    .zip(plane_formats)
+                {
+                    layer.drm_format = drm_format;
+                    layer.num_planes = 1;
+                    layer.object_index[0] = 0;
+                    layer.offset[0] = plane.offset;
+                    layer.pitch[0] = plane.pitch;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use va_backend_sys::VAStatus;
+
+    use super::*;
+
+    const NV12_PLANES: [ExportPlane; 2] = [
+        ExportPlane {
+            offset: 0,
+            pitch: 2048,
+        },
+        ExportPlane {
+            offset: 2048 * 1088,
+            pitch: 2048,
+        },
+    ];
+    const P010_PLANES: [ExportPlane; 2] = [
+        ExportPlane {
+            offset: 0,
+            pitch: 4096,
+        },
+        ExportPlane {
+            offset: 4096 * 1088,
+            pitch: 4096,
+        },
+    ];
+
+    fn image(va_fourcc: u32, planes: &[ExportPlane]) -> ExportedImage<'_> {
+        ExportedImage {
+            va_fourcc,
+            width: 1920,
+            height: 1080,
+            fd: 42,
+            size: 0x60_0000,
+            drm_format_modifier: 0,
+            planes,
+        }
+    }
+
+    fn describe(
+        image: &ExportedImage,
+        layers: ExportLayers,
+    ) -> Result<VADRMPRIMESurfaceDescriptor, VAStatus> {
+        // SAFETY: The descriptor only contains integers.
+        let mut descriptor: VADRMPRIMESurfaceDescriptor = unsafe { std::mem::zeroed() };
+        image
+            .describe(layers, &mut descriptor)
+            .map_err(VAStatus::from)?;
+        Ok(descriptor)
+    }
+
+    fn assert_object(descriptor: &VADRMPRIMESurfaceDescriptor, image: &ExportedImage) {
+        assert_eq!(descriptor.fourcc, image.va_fourcc);
+        assert_eq!((descriptor.width, descriptor.height), (1920, 1080));
+        assert_eq!(descriptor.num_objects, 1);
+        assert_eq!(descriptor.objects[0].fd, 42);
+        assert_eq!(descriptor.objects[0].size, 0x60_0000);
+    }
+
+    fn assert_composed(va_fourcc: u32, planes: &[ExportPlane], drm_format: u32) {
+        let image = image(va_fourcc, planes);
+        let descriptor = describe(&image, ExportLayers::Composed).unwrap();
+        assert_object(&descriptor, &image);
+        assert_eq!(descriptor.num_layers, 1);
+        let layer = &descriptor.layers[0];
+        assert_eq!(layer.drm_format, drm_format);
+        assert_eq!(layer.num_planes, 2);
+        assert_eq!(layer.object_index[..2], [0, 0]);
+        assert_eq!(layer.offset[..2], [planes[0].offset, planes[1].offset]);
+        assert_eq!(layer.pitch[..2], [planes[0].pitch, planes[1].pitch]);
+    }
+
+    fn assert_separate(va_fourcc: u32, planes: &[ExportPlane], drm_formats: [u32; 2]) {
+        let image = image(va_fourcc, planes);
+        let descriptor = describe(&image, ExportLayers::Separate).unwrap();
+        assert_object(&descriptor, &image);
+        assert_eq!(descriptor.num_layers, 2);
+        for ((layer, plane), drm_format) in descriptor.layers.iter().zip(planes).zip(drm_formats) {
+            assert_eq!(layer.drm_format, drm_format);
+            assert_eq!(layer.num_planes, 1);
+            assert_eq!(layer.object_index[0], 0);
+            assert_eq!(layer.offset[0], plane.offset);
+            assert_eq!(layer.pitch[0], plane.pitch);
+        }
+    }
+
+    #[test]
+    fn nv12() {
+        let fourcc = va_backend_sys::VA_FOURCC_NV12;
+        assert_composed(fourcc, &NV12_PLANES, DRM_FORMAT_NV12);
+        assert_separate(fourcc, &NV12_PLANES, [DRM_FORMAT_R8, DRM_FORMAT_GR88]);
+    }
+
+    #[test]
+    fn p010() {
+        let fourcc = va_backend_sys::VA_FOURCC_P010;
+        assert_composed(fourcc, &P010_PLANES, DRM_FORMAT_P010);
+        assert_separate(fourcc, &P010_PLANES, [DRM_FORMAT_R16, DRM_FORMAT_GR1616]);
+    }
+
+    #[test]
+    fn unsupported_fourcc() {
+        let image = image(va_backend_sys::VA_FOURCC_YV12, &NV12_PLANES);
+        assert_eq!(
+            describe(&image, ExportLayers::Composed).map(|_| ()),
+            Err(VaError::InvalidImageFormat.into())
+        );
+    }
+
+    #[test]
+    fn plane_count_mismatch() {
+        let image = image(va_backend_sys::VA_FOURCC_NV12, &NV12_PLANES[..1]);
+        for layers in [ExportLayers::Composed, ExportLayers::Separate] {
+            assert_eq!(
+                describe(&image, layers).map(|_| ()),
+                Err(VaError::InvalidImageFormat.into())
+            );
+        }
+    }
+}
@@ -0,0 +1,71 @@
+//! Ownership of file descriptors.
+//!
+//! The driver handles fds owned by different parties: the DRM fd belongs to libva, dma-buf fds
+//! passed in surface attributes stay owned by the client, exported fds are handed over to the
+//! client, and Vulkan takes ownership of imported fds only if the import succeeds. Mixing these up
+//! leaks fds in long-running compositors or, worse, closes an fd number that has since been reused
+//! by someone else. All conversions between raw and owned fds go through here:
+//!
+//! - fds from the client are only ever borrowed, and duplicated (with `FD_CLOEXEC`, so they don't
+//!   leak into child processes) if they have to outlive the call.
+//! - fds we own are [`OwnedFd`]s until the moment ownership passes to Vulkan or the client.
+
+use std::{
+    ffi::c_int,
+    fs::{File, Metadata},
+    io,
+    mem::ManuallyDrop,
+    os::fd::{AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
+};
+
+use ash::prelude::*;
+
+use crate::VaError;
+
+/// Borrows an fd passed in by libva or the client.
+///
+/// # Safety
+/// `raw` must be negative (rejected with [`VaError::InvalidParameter`]) or an open fd that stays
+/// open for `'a`.
+pub(crate) unsafe fn borrow<'a>(raw: c_int) -> Result<BorrowedFd<'a>, VaError> {
+    if raw < 0 {
+        return Err(VaError::InvalidParameter.context(format!("invalid file descriptor {raw}")));
+    }
+    Ok(unsafe { BorrowedFd::borrow_raw(raw) })
+}
+
+/// Queries the metadata of `fd` without taking ownership of it.
+pub(crate) fn metadata(fd: BorrowedFd) -> io::Result<Metadata> {
+    // SAFETY: The file is never dropped, so the borrowed fd isn't closed, not even on unwinding.
+    let file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd.as_raw_fd()) });
+    file.metadata()
+}
+
+/// Duplicates a borrowed fd that has to be kept beyond the call it was passed to, e.g. a dma-buf
+/// that is imported into Vulkan. The duplicate has `FD_CLOEXEC` set.
+#[allow(dead_code)] // Used once dma-bufs are imported
+pub(crate) fn dup(fd: BorrowedFd) -> Result<OwnedFd, VaError> {
+    fd.try_clone_to_owned().map_err(|err| {
+        VaError::OperationFailed
+            .context(format!("failed to duplicate fd {}: {err}", fd.as_raw_fd()))
+    })
+}
+
+/// Passes `fd` to a Vulkan import (e.g. `VkImportMemoryFdInfoKHR`), which takes ownership of it
+/// only on success. If `import` fails, the fd is closed here.
+#[allow(dead_code)] // Used once dma-bufs are imported
+pub(crate) fn import_into_vulkan<T>(
+    fd: OwnedFd,
+    import: impl FnOnce(RawFd) -> VkResult<T>,
+) -> VkResult<T> {
+    let result = import(fd.as_raw_fd())?;
+    // Owned by the Vulkan object now
+    let _ = fd.into_raw_fd();
+    Ok(result)
+}
+
+/// Hands `fd` over to the client, e.g. in a `VADRMPRIMESurfaceDescriptor`, which closes it.
+#[allow(dead_code)] // Used once surfaces are exported
+pub(crate) fn give_to_client(fd: OwnedFd) -> c_int {
+    fd.into_raw_fd()
+}
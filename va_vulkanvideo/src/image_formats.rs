@@ -0,0 +1,275 @@
+//! Image and subpicture formats reported to clients.
+
+use va_backend_sys::VAImageFormat;
+
+/// Formats returned by vaQueryImageFormats. vaMaxNumImageFormats is derived from this table.
+///
+/// The RGB entries describe their layout with masks on a little-endian 32-bit pixel, the same way
+/// other VA drivers do. Some clients (older GTK/Qt video widgets) pick formats by mask instead of
+/// by fourcc.
+///
+/// NV12 must come first, see [`crate::quirks::Quirks::nv12_only`].
+pub(crate) const IMAGE_FORMATS: [VAImageFormat; 8] = [
+    yuv_format(va_backend_sys::VA_FOURCC_NV12, 12),
+    yuv_format(va_backend_sys::VA_FOURCC_P010, 24),
+    // Several legacy clients only ask vaGetImage for YV12
+    yuv_format(va_backend_sys::VA_FOURCC_YV12, 12),
+    yuv_format(va_backend_sys::VA_FOURCC_I420, 12),
+    rgb_format(va_backend_sys::VA_FOURCC_BGRA, 32, BGR_MASKS, ALPHA_MASK),
+    rgb_format(va_backend_sys::VA_FOURCC_RGBA, 32, RGB_MASKS, ALPHA_MASK),
+    rgb_format(va_backend_sys::VA_FOURCC_BGRX, 24, BGR_MASKS, 0),
+    rgb_format(va_backend_sys::VA_FOURCC_RGBX, 24, RGB_MASKS, 0),
+];
+
+/// Red, green and blue masks of formats with the bytes B, G, R in memory order.
+const BGR_MASKS: [u32; 3] = [0x00ff_0000, 0x0000_ff00, 0x0000_00ff];
+/// Red, green and blue masks of formats with the bytes R, G, B in memory order.
+const RGB_MASKS: [u32; 3] = [0x0000_00ff, 0x0000_ff00, 0x00ff_0000];
+/// Alpha (or padding) is the last byte in memory for all supported RGB formats.
+const ALPHA_MASK: u32 = 0xff00_0000;
+
+/// Formats returned by vaQuerySubpictureFormats, along with their `VA_SUBPICTURE_*` flags.
+/// vaMaxNumSubpictureFormats is derived from this table.
+// TODO: Subpictures aren't implemented yet.
+pub(crate) const SUBPICTURE_FORMATS: [(VAImageFormat, u32); 0] = [];
+
+/// What is stored in a plane of an image.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum PlaneContent {
+    /// Luma samples.
+    Y,
+    /// Cb samples, subsampled 2x2.
+    U,
+    /// Cr samples, subsampled 2x2.
+    V,
+    /// Interleaved Cb/Cr samples, subsampled 2x2.
+    Uv,
+    /// Packed 32-bit RGB(A) pixels.
+    Rgb,
+}
+
+/// Contents of the planes of images with `fourcc`, in VAImage plane order, or `None` for formats
+/// not in [`IMAGE_FORMATS`].
+///
+/// Copy and conversion paths must go through this instead of assuming U comes before V: YV12 is
+/// I420 with the chroma planes swapped.
+pub(crate) const fn planes(fourcc: u32) -> Option<&'static [PlaneContent]> {
+    use PlaneContent::*;
+    match fourcc {
+        va_backend_sys::VA_FOURCC_NV12 | va_backend_sys::VA_FOURCC_P010 => Some(&[Y, Uv]),
+        va_backend_sys::VA_FOURCC_I420 => Some(&[Y, U, V]),
+        va_backend_sys::VA_FOURCC_YV12 => Some(&[Y, V, U]),
+        va_backend_sys::VA_FOURCC_BGRA
+        | va_backend_sys::VA_FOURCC_RGBA
+        | va_backend_sys::VA_FOURCC_BGRX
+        | va_backend_sys::VA_FOURCC_RGBX => Some(&[Rgb]),
+        _ => None,
+    }
+}
+
+/// Plane pitches and offsets of an image, as reported in `VAImage`.
+#[derive(Debug, Copy, Clone)]
+#[allow(dead_code)] // Used once images are created
+pub(crate) struct ImageLayout {
+    pub(crate) num_planes: u32,
+    pub(crate) pitches: [u32; 3],
+    pub(crate) offsets: [u32; 3],
+    pub(crate) data_size: u32,
+}
+
+impl ImageLayout {
+    /// Tightly packed layout of a `width`x`height` image of `format`, or `None` if the format is
+    /// unknown or the image is too large. Dimensions are rounded up to even values, so that
+    /// subsampled chroma planes cover the whole image.
+    #[allow(dead_code)] // Used once images are created
+    pub(crate) fn new(format: &VAImageFormat, width: u32, height: u32) -> Option<Self> {
+        let planes = planes(format.fourcc)?;
+        let sample_size = match format.fourcc {
+            va_backend_sys::VA_FOURCC_P010 => 2,
+            _ => 1,
+        };
+        let width = width.checked_next_multiple_of(2)?;
+        let height = height.checked_next_multiple_of(2)?;
+
+        let mut layout = Self {
+            num_planes: planes.len() as u32,
+            pitches: [0; 3],
+            offsets: [0; 3],
+            data_size: 0,
+        };
+        for (i, plane) in planes.iter().enumerate() {
+            let (pitch, rows) = match plane {
+                PlaneContent::Y => (width.checked_mul(sample_size)?, height),
+                PlaneContent::U | PlaneContent::V => {
+                    ((width / 2).checked_mul(sample_size)?, height / 2)
+                }
+                PlaneContent::Uv => (width.checked_mul(sample_size)?, height / 2),
+                PlaneContent::Rgb => (width.checked_mul(4)?, height),
+            };
+            layout.pitches[i] = pitch;
+            layout.offsets[i] = layout.data_size;
+            layout.data_size = layout.data_size.checked_add(pitch.checked_mul(rows)?)?;
+        }
+        Some(layout)
+    }
+}
+
+const fn yuv_format(fourcc: u32, bits_per_pixel: u32) -> VAImageFormat {
+    VAImageFormat {
+        fourcc,
+        byte_order: va_backend_sys::VA_LSB_FIRST,
+        bits_per_pixel,
+        depth: 0,
+        red_mask: 0,
+        green_mask: 0,
+        blue_mask: 0,
+        alpha_mask: 0,
+        va_reserved: [0; 4],
+    }
+}
+
+/// Packed 32-bit RGB format. `depth` is 24 if the alpha byte is padding.
+const fn rgb_format(
+    fourcc: u32,
+    depth: u32,
+    [red, green, blue]: [u32; 3],
+    alpha: u32,
+) -> VAImageFormat {
+    VAImageFormat {
+        fourcc,
+        byte_order: va_backend_sys::VA_LSB_FIRST,
+        bits_per_pixel: 32,
+        depth,
+        red_mask: red,
+        green_mask: green,
+        blue_mask: blue,
+        alpha_mask: alpha,
+        va_reserved: [0; 4],
+    }
+}
+
+/// Whether all fourccs in `formats` are distinct, so clients can pick a format by fourcc.
+const fn fourccs_are_unique(formats: &[VAImageFormat]) -> bool {
+    let mut i = 0;
+    while i < formats.len() {
+        let mut j = i + 1;
+        while j < formats.len() {
+            if formats[i].fourcc == formats[j].fourcc {
+                return false;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    true
+}
+
+const _: () = assert!(fourccs_are_unique(&IMAGE_FORMATS));
+
+const _: () = assert!(IMAGE_FORMATS[0].fourcc == va_backend_sys::VA_FOURCC_NV12);
+
+/// Every reported format needs a known plane layout.
+const _: () = {
+    let mut i = 0;
+    while i < IMAGE_FORMATS.len() {
+        assert!(planes(IMAGE_FORMATS[i].fourcc).is_some());
+        i += 1;
+    }
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(fourcc: u32, width: u32, height: u32) -> Option<(Vec<u32>, Vec<u32>, u32)> {
+        let format = IMAGE_FORMATS
+            .iter()
+            .find(|format| format.fourcc == fourcc)
+            .unwrap_or_else(|| panic!("{fourcc:#x} isn't an image format"));
+        ImageLayout::new(format, width, height).map(|layout| {
+            let num_planes = layout.num_planes as usize;
+            (
+                layout.pitches[..num_planes].to_vec(),
+                layout.offsets[..num_planes].to_vec(),
+                layout.data_size,
+            )
+        })
+    }
+
+    #[test]
+    fn chroma_plane_order() {
+        use PlaneContent::*;
+        assert_eq!(planes(va_backend_sys::VA_FOURCC_I420), Some(&[Y, U, V][..]));
+        assert_eq!(planes(va_backend_sys::VA_FOURCC_YV12), Some(&[Y, V, U][..]));
+        assert_eq!(planes(va_backend_sys::VA_FOURCC_NV12), Some(&[Y, Uv][..]));
+        assert_eq!(planes(va_backend_sys::VA_FOURCC_P016), None);
+    }
+
+    #[test]
+    fn planar_420() {
+        // Both have the same layout, only the contents of the chroma planes are swapped
+        for fourcc in [
+            va_backend_sys::VA_FOURCC_I420,
+            va_backend_sys::VA_FOURCC_YV12,
+        ] {
+            assert_eq!(
+                layout(fourcc, 1920, 1080),
+                Some((
+                    vec![1920, 960, 960],
+                    vec![0, 2_073_600, 2_592_000],
+                    3_110_400
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn nv12() {
+        assert_eq!(
+            layout(va_backend_sys::VA_FOURCC_NV12, 1920, 1080),
+            Some((vec![1920, 1920], vec![0, 2_073_600], 3_110_400))
+        );
+    }
+
+    #[test]
+    fn p010() {
+        assert_eq!(
+            layout(va_backend_sys::VA_FOURCC_P010, 1920, 1080),
+            Some((vec![3840, 3840], vec![0, 4_147_200], 6_220_800))
+        );
+    }
+
+    #[test]
+    fn rgb() {
+        assert_eq!(
+            layout(va_backend_sys::VA_FOURCC_BGRA, 4, 2),
+            Some((vec![16], vec![0], 32))
+        );
+    }
+
+    #[test]
+    fn odd_dimensions_rounded_to_even() {
+        assert_eq!(
+            layout(va_backend_sys::VA_FOURCC_NV12, 5, 3),
+            Some((vec![6, 6], vec![0, 24], 36))
+        );
+        assert_eq!(
+            layout(va_backend_sys::VA_FOURCC_YV12, 5, 3),
+            Some((vec![6, 3, 3], vec![0, 24, 30], 36))
+        );
+    }
+
+    #[test]
+    fn overflow() {
+        assert_eq!(layout(va_backend_sys::VA_FOURCC_NV12, u32::MAX, 2), None);
+        assert_eq!(layout(va_backend_sys::VA_FOURCC_NV12, 65536, 65536), None);
+        assert_eq!(layout(va_backend_sys::VA_FOURCC_P010, 0x8000_0000, 2), None);
+        assert_eq!(layout(va_backend_sys::VA_FOURCC_BGRA, 0x4000_0000, 2), None);
+    }
+
+    #[test]
+    fn unknown_format() {
+        let format = yuv_format(va_backend_sys::VA_FOURCC_P016, 24);
+        assert!(ImageLayout::new(&format, 16, 16).is_none());
+    }
+}
@@ -0,0 +1,160 @@
+//! The Vulkan instance shared by all displays of a process.
+//!
+//! Browsers create a VADisplay per decoder, sometimes dozens of them. Creating a Vulkan instance
+//! (with validation layers) and probing all physical devices for each of them makes display
+//! initialization slow, so this is done once per process instead. The instance is dropped again
+//! once the last display using it is terminated, since libva unloads the driver afterwards.
+//!
+//! Displays on different DRM devices (e.g. the iGPU and dGPU of a laptop) share the instance as
+//! well, each with its own physical device. Everything here, including the debug messenger, is
+//! shared between the displays and has to stay free of per-display state.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, Once, PoisonError, TryLockError, Weak},
+};
+
+use ash::{ext, prelude::*, vk};
+use log::{debug, warn};
+
+use crate::{
+    DeviceId, PhysicalDeviceInfo, build_info, probe_physical_device, teardown,
+    vulkan_debug_callback,
+};
+
+static SHARED_INSTANCE: Mutex<Weak<SharedInstance>> = Mutex::new(Weak::new());
+
+pub(crate) struct SharedInstance {
+    pub(crate) entry: ash::Entry,
+    pub(crate) instance: ash::Instance,
+    debug_utils_loader: ext::debug_utils::Instance,
+    debug_call_back: vk::DebugUtilsMessengerEXT,
+    /// Probed physical devices, keyed by the DRM device of the display.
+    physical_devices: Mutex<HashMap<DeviceId, PhysicalDeviceInfo>>,
+}
+
+impl SharedInstance {
+    /// Returns the instance of this process, creating it if no display currently holds it.
+    pub(crate) fn get() -> VkResult<Arc<Self>> {
+        let mut shared = SHARED_INSTANCE
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        if let Some(instance) = shared.upgrade() {
+            debug!(
+                "Reusing existing Vulkan instance, used by {} other displays",
+                Arc::strong_count(&instance) - 1
+            );
+            return Ok(instance);
+        }
+
+        let instance = Arc::new(Self::create()?);
+        *shared = Arc::downgrade(&instance);
+
+        static REGISTER_TEARDOWN: Once = Once::new();
+        REGISTER_TEARDOWN.call_once(|| teardown::at_unload(Self::on_unload));
+
+        Ok(instance)
+    }
+
+    /// Teardown hook reporting displays that were never terminated.
+    ///
+    /// Their instance is deliberately leaked: other threads of the host may still be using it, and
+    /// at exit the Vulkan driver may already have run its own destructors.
+    ///
+    /// The report is skipped if another thread holds the lock, e.g. one that is still creating a
+    /// display or was stopped by `exit` while holding it. Waiting for it could hang the process.
+    fn on_unload() {
+        let shared = match SHARED_INSTANCE.try_lock() {
+            Ok(shared) => shared,
+            Err(TryLockError::Poisoned(err)) => err.into_inner(),
+            Err(TryLockError::WouldBlock) => return,
+        };
+        let displays = shared.strong_count();
+        if displays > 0 {
+            warn!("{displays} displays were not terminated, leaking the Vulkan instance");
+        }
+    }
+
+    fn create() -> VkResult<Self> {
+        let entry = ash::Entry::linked();
+
+        let app_info = vk::ApplicationInfo::default()
+            .application_name(c"Vulkan Video VA-API Driver")
+            .application_version(0)
+            .engine_name(build_info::DRIVER_NAME)
+            .engine_version(build_info::VK_VERSION)
+            .api_version(vk::API_VERSION_1_3);
+
+        let layer_names = vec![c"VK_LAYER_KHRONOS_validation".as_ptr()];
+        let extension_names = vec![ext::debug_utils::NAME.as_ptr()];
+
+        let mut debug_info = vk::DebugUtilsMessengerCreateInfoEXT::default()
+            .message_severity(
+                vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE
+                    | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+                    | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
+                    | vk::DebugUtilsMessageSeverityFlagsEXT::INFO,
+            )
+            .message_type(
+                vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+                    | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+                    | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+            )
+            .pfn_user_callback(Some(vulkan_debug_callback));
+
+        let create_info = vk::InstanceCreateInfo::default()
+            .application_info(&app_info)
+            .enabled_layer_names(&layer_names)
+            .enabled_extension_names(&extension_names)
+            .push_next(&mut debug_info);
+
+        let instance = unsafe { entry.create_instance(&create_info, None)? };
+        debug!("Vulkan instance created successfully");
+
+        let debug_utils_loader = ext::debug_utils::Instance::new(&entry, &instance);
+        let debug_call_back =
+            unsafe { debug_utils_loader.create_debug_utils_messenger(&debug_info, None)? };
+        debug!("Debug utils messenger created successfully");
+
+        Ok(Self {
+            entry,
+            instance,
+            debug_utils_loader,
+            debug_call_back,
+            physical_devices: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Returns the physical device matching the DRM device `device_id`, probing the physical
+    /// devices only the first time a display is opened on it.
+    pub(crate) fn physical_device(&self, device_id: DeviceId) -> VkResult<PhysicalDeviceInfo> {
+        let mut physical_devices = self
+            .physical_devices
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        if let Some(info) = physical_devices.get(&device_id) {
+            debug!(
+                "Reusing physical device probed for DRM device {}/{}",
+                device_id.0, device_id.1
+            );
+            return Ok(info.clone());
+        }
+
+        let info = probe_physical_device(&self.entry, &self.instance, device_id)?;
+        physical_devices.insert(device_id, info.clone());
+        Ok(info)
+    }
+}
+
+impl Drop for SharedInstance {
+    fn drop(&mut self) {
+        debug!("Destroying Vulkan instance");
+        unsafe {
+            self.debug_utils_loader
+                .destroy_debug_utils_messenger(self.debug_call_back, None);
+            self.instance.destroy_instance(None);
+        }
+    }
+}
@@ -1,13 +1,46 @@
+// TODO: vavk-bench, a binary feeding Annex B/IVF files through the decode path directly (without
+// libva), reporting fps, per-stage latency and VRAM use, for comparisons against other VA drivers
+// and catching performance regressions. Needs the decode path to be reachable from outside the
+// cdylib, e.g. by splitting it into an rlib.
+
+mod attrib_dump;
+mod av1_obu;
+mod barriers;
+mod buffer_types;
+mod build_info;
+mod checksum;
+mod config;
+mod error;
+mod export;
+mod fd;
+mod image_formats;
+mod instance;
+mod memory_budget;
+mod objects;
+mod parameter_sets;
+mod pipeline_cache;
+mod quirks;
+mod rate_stats;
+mod self_test;
+mod session_memory;
+mod slow_paths;
+mod staging;
+mod submit_batch;
+mod surfaces;
+mod teardown;
+mod video_caps;
+mod vpp;
+mod watchdog;
+mod workarounds;
+
 use std::{
     borrow::Cow,
     ffi::{CStr, c_float, c_int, c_short, c_uchar, c_uint, c_ulong, c_ushort, c_void},
     fmt,
-    fs::File,
-    os::{
-        fd::{FromRawFd, IntoRawFd, RawFd},
-        linux::fs::MetadataExt,
-        unix::fs::FileTypeExt,
-    },
+    os::{linux::fs::MetadataExt, unix::fs::FileTypeExt},
+    panic::{self, AssertUnwindSafe, Location},
+    sync::{Arc, Mutex, Once},
+    time::Duration,
 };
 
 use ash::{
@@ -18,28 +51,83 @@ use ash::{
 use log::{debug, error, info, trace, warn};
 use simple_logger::SimpleLogger;
 
+use config::Config;
+use error::VaError;
+use instance::SharedInstance;
+use memory_budget::MemoryBudget;
+use objects::ObjectTable;
+use quirks::Quirks;
+use rate_stats::RateStats;
+use video_caps::{EncodeCapabilities, VideoCapabilities};
+use workarounds::Workarounds;
+
 use va_backend_sys::{
     VA_STATUS_SUCCESS, VABufferID, VABufferType, VAConfigAttrib, VAConfigID, VAContextID,
-    VADisplayAttribute, VADriverContext, VADriverContextP, VADriverInit, VADriverVTable,
-    VAEntrypoint, VAImage, VAImageFormat, VAImageID, VAProfile, VAStatus, VASubpictureID,
-    VASurfaceID, VASurfaceStatus, drm_state,
+    VADRMPRIMESurfaceDescriptor, VADisplayAttribType, VADisplayAttribute, VADriverContext,
+    VADriverContextP, VADriverInit, VADriverVTable, VAEntrypoint, VAImage, VAImageFormat,
+    VAImageID, VAProfile, VAStatus, VASubpictureID, VASurfaceAttrib, VASurfaceID, VASurfaceStatus,
+    drm_state,
 };
 
+/// Runs `f`, turning a panic into [`VaError::OperationFailed`].
+///
+/// Unwinding out of an `extern "C"` function aborts the process, so a bug in the driver would
+/// take down the host browser or compositor instead of failing a single call.
+fn catch_panic<T>(f: impl FnOnce() -> Result<T, VaError>) -> Result<T, VaError> {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("(no message)");
+        error!("Caught panic at the FFI boundary: {message}");
+        Err(VaError::OperationFailed.context("driver function panicked"))
+    })
+}
+
+/// Runs `f` with the driver context, converting its result to a `VAStatus`. Errors are logged
+/// along with the location of the calling driver function, panics are caught by [`catch_panic`].
+#[track_caller]
 fn with_driver_context(
     driver_context: VADriverContextP,
     f: impl FnOnce(&mut VADriverContext) -> Result<(), VaError>,
 ) -> VAStatus {
-    let driver_context = unsafe { driver_context_as_ref(driver_context) };
-    let driver_context = match driver_context {
-        Ok(ctx) => ctx,
-        Err(e) => return e.into(),
-    };
-    f(driver_context)
-        .map(|_| VA_STATUS_SUCCESS as VAStatus)
-        .unwrap_or_else(|e| e.into())
+    let location = Location::caller();
+    let result = catch_panic(|| unsafe { driver_context_as_ref(driver_context) }.and_then(f));
+    match result {
+        Ok(()) => VA_STATUS_SUCCESS as VAStatus,
+        Err(err) => {
+            err.log_returned(location);
+            err.into()
+        }
+    }
+}
+
+/// Copies `items` into a caller-provided array that libva sized with `capacity`, one of the
+/// vaMaxNum* values from the driver context.
+///
+/// The capacity is checked on every write instead of trusting that our tables and the max_*
+/// values we set in [`va_driver_init`] stay in sync. If they don't, this fails with
+/// [`VaError::MaxNumExceeded`] rather than writing past the end of the array.
+///
+/// # Safety
+///
+/// `list` must be valid for writes of `capacity` elements.
+unsafe fn write_list<T: Copy>(list: *mut T, capacity: c_int, items: &[T]) -> Result<(), VaError> {
+    if items.len() > usize::try_from(capacity).unwrap_or(0) {
+        return Err(VaError::MaxNumExceeded.context(format!(
+            "{} entries don't fit into an array of {capacity}",
+            items.len()
+        )));
+    }
+    unsafe { list.copy_from_nonoverlapping(items.as_ptr(), items.len()) };
+    Ok(())
 }
 
 extern "C" fn va_terminate(driver_context: VADriverContextP) -> VAStatus {
+    // TODO: Some clients destroy and recreate the display on monitor hotplug. Once surfaces can be
+    // exported, keep dma-buf-backed surfaces (and the Vulkan state they depend on) alive here so a
+    // re-initialized display can adopt them instead of reallocating the whole pipeline.
     with_driver_context(driver_context, |driver_context| {
         let driver_data = std::mem::take(&mut driver_context.pDriverData);
         if !driver_data.is_null() {
@@ -94,17 +182,19 @@ extern "C" fn va_query_config_profiles(
             supported_profiles.push(va_backend_sys::VAProfile_VAProfileVP9Profile3);
         }
 
-        if supported_profiles.len() > driver_context.max_profiles as usize {
-            // Should never happen, max_profiles is normally only set by us
-            return Err(VaError::OperationFailed);
-        }
+        // Drop profiles whose codec is supported, but not the profile itself
+        supported_profiles
+            .retain(|&profile| supported_entrypoints(&driver_data.vulkan, profile).is_ok());
 
         // SAFETY: Null/unaligned checks are done above. Docs state:
         // > The caller must provide a "profile_list" array that can hold at least
         // > vaMaxNumProfile() entries.
         unsafe {
-            profile_list
-                .copy_from_nonoverlapping(supported_profiles.as_ptr(), supported_profiles.len());
+            write_list(
+                profile_list,
+                driver_context.max_profiles,
+                &supported_profiles,
+            )?;
             *num_profiles = supported_profiles.len() as c_int;
         }
 
@@ -114,6 +204,60 @@ extern "C" fn va_query_config_profiles(
 
 const MAX_ENTRYPOINTS: usize = 2; // Decode and Encode
 
+/// Entrypoints supported for `profile`.
+///
+/// Decoding is only supported if the implementation has capabilities for the profile, including
+/// an output format we can use for surfaces, see [`VideoCapabilities::query_decode`].
+fn supported_entrypoints(
+    vulkan: &VulkanData,
+    profile: VAProfile,
+) -> Result<&'static [VAEntrypoint], VaError> {
+    let codecs = &vulkan.supported_codecs;
+    let (decode, encode) = match profile {
+        va_backend_sys::VAProfile_VAProfileH264Baseline
+        | va_backend_sys::VAProfile_VAProfileH264ConstrainedBaseline
+        | va_backend_sys::VAProfile_VAProfileH264Main
+        | va_backend_sys::VAProfile_VAProfileH264High => (codecs.h264_decode, codecs.h264_encode),
+        va_backend_sys::VAProfile_VAProfileHEVCMain
+        | va_backend_sys::VAProfile_VAProfileHEVCMain10 => (codecs.h265_decode, codecs.h265_encode),
+        va_backend_sys::VAProfile_VAProfileAV1Profile0
+        | va_backend_sys::VAProfile_VAProfileAV1Profile1 => (codecs.av1_decode, codecs.av1_encode),
+        va_backend_sys::VAProfile_VAProfileVP9Profile0
+        | va_backend_sys::VAProfile_VAProfileVP9Profile1
+        | va_backend_sys::VAProfile_VAProfileVP9Profile2
+        | va_backend_sys::VAProfile_VAProfileVP9Profile3 => (
+            codecs.vp9_decode,
+            false, // No VP9 encode support
+        ),
+        // TODO: VK_KHR_video_decode_h264 has no multiview support (no MVC profile_idc, no
+        // inter-view references). Revisit if an extension for it appears, until then 3D Blu-ray
+        // playback paths that probe these profiles fall back to 2D.
+        va_backend_sys::VAProfile_VAProfileH264MultiviewHigh
+        | va_backend_sys::VAProfile_VAProfileH264StereoHigh => {
+            return Err(VaError::UnsupportedProfile);
+        }
+        _ => return Err(VaError::UnsupportedProfile),
+    };
+    let decode = decode && vulkan.decode_capabilities(profile).is_some();
+    let encode = encode && vulkan.encode_capabilities(profile).is_some();
+
+    const ENTRYPOINTS: [VAEntrypoint; MAX_ENTRYPOINTS] = [
+        va_backend_sys::VAEntrypoint_VAEntrypointVLD,
+        va_backend_sys::VAEntrypoint_VAEntrypointEncSlice,
+    ];
+    let range = if decode && encode {
+        0..2
+    } else if decode {
+        0..1
+    } else if encode {
+        1..2
+    } else {
+        return Err(VaError::UnsupportedProfile);
+    };
+
+    Ok(&ENTRYPOINTS[range])
+}
+
 extern "C" fn va_query_config_entrypoints(
     driver_context: VADriverContextP,
     profile: VAProfile,
@@ -129,60 +273,17 @@ extern "C" fn va_query_config_entrypoints(
 
     with_driver_context(driver_context, |driver_context| {
         let driver_data = unsafe { DriverData::from_ptr(driver_context.pDriverData)? };
-        let (decode, encode) = match profile {
-            va_backend_sys::VAProfile_VAProfileH264Baseline
-            | va_backend_sys::VAProfile_VAProfileH264ConstrainedBaseline
-            | va_backend_sys::VAProfile_VAProfileH264Main
-            | va_backend_sys::VAProfile_VAProfileH264High => (
-                driver_data.vulkan.supported_codecs.h264_decode,
-                driver_data.vulkan.supported_codecs.h264_encode,
-            ),
-            va_backend_sys::VAProfile_VAProfileHEVCMain
-            | va_backend_sys::VAProfile_VAProfileHEVCMain10 => (
-                driver_data.vulkan.supported_codecs.h265_decode,
-                driver_data.vulkan.supported_codecs.h265_encode,
-            ),
-            va_backend_sys::VAProfile_VAProfileAV1Profile0
-            | va_backend_sys::VAProfile_VAProfileAV1Profile1 => (
-                driver_data.vulkan.supported_codecs.av1_decode,
-                driver_data.vulkan.supported_codecs.av1_encode,
-            ),
-            va_backend_sys::VAProfile_VAProfileVP9Profile0
-            | va_backend_sys::VAProfile_VAProfileVP9Profile1
-            | va_backend_sys::VAProfile_VAProfileVP9Profile2
-            | va_backend_sys::VAProfile_VAProfileVP9Profile3 => (
-                driver_data.vulkan.supported_codecs.vp9_decode,
-                false, // No VP9 encode support
-            ),
-            _ => return Err(VaError::UnsupportedProfile),
-        };
-
-        if MAX_ENTRYPOINTS > driver_context.max_entrypoints as usize {
-            // Should never happen, max_entrypoints is normally only set by us
-            return Err(VaError::OperationFailed);
-        }
-
-        let entry_points = [
-            va_backend_sys::VAEntrypoint_VAEntrypointVLD,
-            va_backend_sys::VAEntrypoint_VAEntrypointEncSlice,
-        ];
-        let range = if decode && encode {
-            0..2
-        } else if decode {
-            0..1
-        } else if encode {
-            1..2
-        } else {
-            // Shouldn't happen, as the profile wouldn't be listed in the first place
-            return Err(VaError::UnsupportedProfile);
-        };
-        let entry_points = &entry_points[range];
+        let entry_points = supported_entrypoints(&driver_data.vulkan, profile)?;
 
         // SAFETY: Null/unaligned checks are done above. Docs state:
-        // > The caller must provide a "profile_list" array that can hold at least
-        // > vaMaxNumProfile() entries.
+        // > The caller must provide an "entrypoint_list" array that can hold at least
+        // > vaMaxNumEntrypoints() entries.
         unsafe {
-            entrypoint_list.copy_from_nonoverlapping(entry_points.as_ptr(), entry_points.len());
+            write_list(
+                entrypoint_list,
+                driver_context.max_entrypoints,
+                entry_points,
+            )?;
             *num_entrypoints = entry_points.len() as c_int;
         }
 
@@ -190,66 +291,246 @@ extern "C" fn va_query_config_entrypoints(
     })
 }
 
+/// attrib_list:
+/// > Attributes not set by the client are set to their default values by the driver.
 extern "C" fn va_create_config(
     driver_context: VADriverContextP,
-    _profile: VAProfile,
-    _entrypoint: VAEntrypoint,
-    _attrib_list: *mut VAConfigAttrib,
-    _num_attribs: c_int,
-    _config_id: *mut VAConfigID, // out
+    profile: VAProfile,
+    entrypoint: VAEntrypoint,
+    attrib_list: *mut VAConfigAttrib,
+    num_attribs: c_int,
+    config_id: *mut VAConfigID, // out
 ) -> VAStatus {
-    with_driver_context(driver_context, |_driver_context| {
-        Err(VaError::Unimplemented)
+    if num_attribs < 0 || (num_attribs > 0 && (attrib_list.is_null() || !attrib_list.is_aligned()))
+    {
+        return VaError::InvalidParameter.into();
+    }
+    if config_id.is_null() || !config_id.is_aligned() {
+        return VaError::InvalidParameter.into();
+    }
+
+    with_driver_context(driver_context, |driver_context| {
+        let driver_data = unsafe { DriverData::from_ptr(driver_context.pDriverData)? };
+
+        // SAFETY: Null/unaligned checks are done above, the caller provides `num_attribs`
+        // attributes.
+        let requested = if num_attribs == 0 {
+            &[][..]
+        } else {
+            unsafe { std::slice::from_raw_parts(attrib_list, num_attribs as usize) }
+        };
+
+        let config = Config::new(&driver_data.vulkan, profile, entrypoint, requested);
+        if attrib_dump::enabled() {
+            attrib_dump::config_attribs("vaCreateConfig", "requested", requested);
+            attrib_dump::result("vaCreateConfig", &config);
+            if let Ok(config) = &config {
+                attrib_dump::config_attribs("vaCreateConfig", "config", &config.attribs);
+            }
+        }
+        let config = config?;
+        let id = driver_data
+            .configs
+            .insert(config)
+            .ok_or(VaError::MaxNumExceeded)?;
+
+        unsafe { *config_id = id };
+        Ok(())
     })
 }
 
 extern "C" fn va_destroy_config(
     driver_context: VADriverContextP,
-    _config_id: VAConfigID,
+    config_id: VAConfigID,
 ) -> VAStatus {
-    with_driver_context(driver_context, |_driver_context| {
-        Err(VaError::Unimplemented)
+    with_driver_context(driver_context, |driver_context| {
+        let driver_data = unsafe { DriverData::from_ptr(driver_context.pDriverData)? };
+        driver_data
+            .configs
+            .remove(config_id)
+            .map(|_| ())
+            .ok_or_else(|| VaError::InvalidConfig.object(config_id))
     })
 }
 
+/// attrib_list:
+/// > The caller must provide an "attrib_list" with all attributes to be retrieved.  Upon return,
+/// > the attributes in "attrib_list" have been updated with their value.  Unknown attributes or
+/// > attributes that are not supported for the given profile/entrypoint pair will have their
+/// > value set to VA_ATTRIB_NOT_SUPPORTED
 extern "C" fn va_get_config_attributes(
     driver_context: VADriverContextP,
-    _profile: VAProfile,
-    _entrypoint: VAEntrypoint,
-    _attrib_list: *mut VAConfigAttrib, // in/out
-    _num_attribs: c_int,
+    profile: VAProfile,
+    entrypoint: VAEntrypoint,
+    attrib_list: *mut VAConfigAttrib, // in/out
+    num_attribs: c_int,
 ) -> VAStatus {
-    with_driver_context(driver_context, |_driver_context| {
-        Err(VaError::Unimplemented)
+    if num_attribs < 0 || (num_attribs > 0 && (attrib_list.is_null() || !attrib_list.is_aligned()))
+    {
+        return VaError::InvalidParameter.into();
+    }
+
+    with_driver_context(driver_context, |driver_context| {
+        let driver_data = unsafe { DriverData::from_ptr(driver_context.pDriverData)? };
+        config::check_profile_entrypoint(&driver_data.vulkan, profile, entrypoint)?;
+
+        if num_attribs == 0 {
+            return Ok(());
+        }
+
+        // SAFETY: Null/unaligned checks are done above, the caller provides `num_attribs`
+        // attributes.
+        let attribs = unsafe { std::slice::from_raw_parts_mut(attrib_list, num_attribs as usize) };
+        for attrib in attribs.iter_mut() {
+            attrib.value =
+                config::supported_value(&driver_data.vulkan, profile, entrypoint, attrib.type_)
+                    .unwrap_or(va_backend_sys::VA_ATTRIB_NOT_SUPPORTED);
+        }
+        if attrib_dump::enabled() {
+            attrib_dump::config_attribs("vaGetConfigAttributes", "returned", attribs);
+        }
+
+        Ok(())
     })
 }
 
 extern "C" fn va_query_config_attributes(
     driver_context: VADriverContextP,
-    _config_id: VAConfigID,
-    _profile: *mut VAProfile,          // out
-    _entrypoint: *mut VAEntrypoint,    // out
-    _attrib_list: *mut VAConfigAttrib, // out
-    _num_attribs: *mut c_int,          // out
+    config_id: VAConfigID,
+    profile: *mut VAProfile,          // out
+    entrypoint: *mut VAEntrypoint,    // out
+    attrib_list: *mut VAConfigAttrib, // out
+    num_attribs: *mut c_int,          // out
 ) -> VAStatus {
-    with_driver_context(driver_context, |_driver_context| {
-        Err(VaError::Unimplemented)
+    if profile.is_null() || !profile.is_aligned() {
+        return VaError::InvalidParameter.into();
+    }
+    if entrypoint.is_null() || !entrypoint.is_aligned() {
+        return VaError::InvalidParameter.into();
+    }
+    if attrib_list.is_null() || !attrib_list.is_aligned() {
+        return VaError::InvalidParameter.into();
+    }
+    if num_attribs.is_null() || !num_attribs.is_aligned() {
+        return VaError::InvalidParameter.into();
+    }
+
+    with_driver_context(driver_context, |driver_context| {
+        let driver_data = unsafe { DriverData::from_ptr(driver_context.pDriverData)? };
+        let config = driver_data
+            .configs
+            .get(config_id)
+            .ok_or_else(|| VaError::InvalidConfig.object(config_id))?;
+
+        // SAFETY: Null/unaligned checks are done above. Docs state:
+        // > The caller must provide an "attrib_list" array large enough to hold
+        // > vaMaxNumConfigAttributes() entries.
+        unsafe {
+            *profile = config.profile;
+            *entrypoint = config.entrypoint;
+            write_list(attrib_list, driver_context.max_attributes, &config.attribs)?;
+            *num_attribs = config.attribs.len() as c_int;
+        }
+
+        Ok(())
     })
 }
 
+/// Legacy version of vaCreateSurfaces2 without attributes. libva only calls this if
+/// vaCreateSurfaces2 is missing, but some compatibility shims still call it directly.
 extern "C" fn va_create_surfaces(
     driver_context: VADriverContextP,
-    _width: c_int,
-    _height: c_int,
-    _format: c_int,
-    _num_surfaces: c_int,
-    _surfaces: *mut VASurfaceID, // out
+    width: c_int,
+    height: c_int,
+    format: c_int,
+    num_surfaces: c_int,
+    surfaces: *mut VASurfaceID, // out
 ) -> VAStatus {
-    with_driver_context(driver_context, |_driver_context| {
-        Err(VaError::Unimplemented)
+    if num_surfaces <= 0 || surfaces.is_null() || !surfaces.is_aligned() {
+        return VaError::InvalidParameter.into();
+    }
+
+    with_driver_context(driver_context, |driver_context| {
+        let driver_data = unsafe { DriverData::from_ptr(driver_context.pDriverData)? };
+        let request = surfaces::SurfaceRequest::legacy(format, width, height)?;
+
+        // SAFETY: Null/unaligned checks are done above, the caller provides room for
+        // `num_surfaces` surfaces.
+        let surfaces = unsafe { std::slice::from_raw_parts_mut(surfaces, num_surfaces as usize) };
+        create_surfaces(driver_data, &request, surfaces)
+    })
+}
+
+/// format:
+/// > the desired surface format. See VA_RT_FORMAT_*
+///
+/// attrib_list:
+/// > the list of attributes, or NULL if num_attribs is zero.
+extern "C" fn va_create_surfaces2(
+    driver_context: VADriverContextP,
+    format: c_uint,
+    width: c_uint,
+    height: c_uint,
+    surfaces: *mut VASurfaceID, // out
+    num_surfaces: c_uint,
+    attrib_list: *mut VASurfaceAttrib,
+    num_attribs: c_uint,
+) -> VAStatus {
+    if num_surfaces == 0 || surfaces.is_null() || !surfaces.is_aligned() {
+        return VaError::InvalidParameter.into();
+    }
+    if num_attribs > 0 && (attrib_list.is_null() || !attrib_list.is_aligned()) {
+        return VaError::InvalidParameter.into();
+    }
+
+    with_driver_context(driver_context, |driver_context| {
+        let driver_data = unsafe { DriverData::from_ptr(driver_context.pDriverData)? };
+
+        // SAFETY: Null/unaligned checks are done above, the caller provides `num_attribs`
+        // attributes.
+        let attribs = if num_attribs == 0 {
+            &[][..]
+        } else {
+            unsafe { std::slice::from_raw_parts(attrib_list, num_attribs as usize) }
+        };
+        if attrib_dump::enabled() {
+            attrib_dump::surface_attribs("vaCreateSurfaces2", "requested", attribs);
+        }
+
+        // SAFETY: Null/unaligned checks are done above, the caller provides room for
+        // `num_surfaces` surfaces.
+        let surfaces = unsafe { std::slice::from_raw_parts_mut(surfaces, num_surfaces as usize) };
+        let result = surfaces::SurfaceRequest::new(format, width, height, attribs)
+            .and_then(|request| create_surfaces(driver_data, &request, surfaces));
+        if attrib_dump::enabled() {
+            attrib_dump::result("vaCreateSurfaces2", &result);
+        }
+        result
     })
 }
 
+/// Allocates a surface for each entry of `surfaces` and stores its ID there.
+fn create_surfaces(
+    _driver_data: &mut DriverData,
+    _request: &surfaces::SurfaceRequest,
+    _surfaces: &mut [VASurfaceID],
+) -> Result<(), VaError> {
+    // TODO: Besides dma-buf import, this should accept a driver-specific VASurfaceAttribMemoryType
+    // for Vulkan opaque fd memory (VK_KHR_external_memory_fd), so apps that already manage VkImages
+    // (wgpu, Vulkan compositors) can hand them to the encoder without going through dma-buf.
+    // TODO: Track the color range and primaries of each surface, set from the SPS VUI when
+    // decoding and from the VPP output parameters otherwise. The CSC shaders need them
+    // (VkSamplerYcbcrRange/ModelConversion), and exports should carry them, so full-range JPEG
+    // output isn't treated as limited range and washed out downstream.
+    // TODO: If the decode output can't be in the requested VASurfaceAttribPixelFormat (e.g. I420
+    // when the decoder only writes NV12), allocate a supported format instead of failing, and
+    // convert on access in vaDeriveImage/vaGetImage/vaExportSurfaceHandle.
+    // TODO: Surfaces whose VASurfaceAttribUsageHint is only DECODER|ENCODER (transcoding without
+    // display) never need the separate decode output picture or format conversions. Keep them in
+    // the DPB-compatible format and skip the output copy to save memory bandwidth.
+    Err(VaError::Unimplemented)
+}
+
 extern "C" fn va_destroy_surfaces(
     driver_context: VADriverContextP,
     _surface_list: *mut VASurfaceID,
@@ -270,6 +551,20 @@ extern "C" fn va_create_context(
     _num_render_targets: c_int,
     _context: *mut VAContextID, // out
 ) -> VAStatus {
+    // TODO: Chromium opens a decoder per tab, so dozens of video sessions can be alive at once.
+    // Once sessions are created here, bind their memory from a per-display pool bucketed by size
+    // class (the requirements of vkGetVideoSessionMemoryRequirementsKHR only vary with profile and
+    // max coded extent), so sessions of the same kind reuse allocations instead of fragmenting
+    // device memory. Cap the pool through MemoryBudget and evict the scratch memory of the least
+    // recently used idle sessions when the cap is hit.
+    // TODO: Some clients allocate render targets of the display size (the conformance window)
+    // rather than the coded extent, e.g. 1920x1080 for a 1920x1088 stream. Instead of failing,
+    // decode those into internal pictures of the coded extent (see VideoCapabilities::coded_extent)
+    // and blit the conformance window into the client surface.
+    // TODO: Likewise for encode, accept picture sizes that aren't MB/CTU aligned: pad the input
+    // to the coded extent (replicating the last row/column) and set the SPS frame cropping
+    // (H.264), conformance window (H.265) or render size (AV1) accordingly, instead of requiring
+    // clients to pre-pad their input.
     with_driver_context(driver_context, |_driver_context| {
         Err(VaError::Unimplemented)
     })
@@ -293,6 +588,11 @@ extern "C" fn va_create_buffer(
     _data: *mut c_void,    // in
     _buf_id: *mut VABufferID,
 ) -> VAStatus {
+    // TODO: Two-pass encoding. Vulkan rate control has no notion of passes or first-pass stats,
+    // so it would be built on top: in the first pass, collect per-frame complexity (bitstream
+    // size at a fixed QP) into a stats buffer of a driver-specific buffer type, and in the second
+    // pass encode with rate control disabled and per-frame QPs allocated from those stats.
+    // TODO: Validate the type and size with buffer_types::lookup for the context's config.
     with_driver_context(driver_context, |_driver_context| {
         Err(VaError::Unimplemented)
     })
@@ -313,6 +613,14 @@ extern "C" fn va_map_buffer(
     _buf_id: VABufferID,     // in
     _pbuf: *mut *mut c_void, // out
 ) -> VAStatus {
+    // TODO: Allocate coded buffers from staging::direct_mapped_memory_type when available and
+    // return their persistent mapping here, after waiting for the encode to finish. Only fall back
+    // to copying the bitstream out of device memory without resizable BAR.
+    // TODO: ffmpeg reuses its coded buffers across frames instead of recreating them, as the
+    // Intel driver allows. When a coded buffer is the target of a new picture, reset its query
+    // slot and the status/size of its VACodedBufferSegment, and if the previous encode into it is
+    // still in flight, fail here and in vaRenderPicture with SurfaceBusy rather than handing out
+    // a half-written bitstream.
     with_driver_context(driver_context, |_driver_context| {
         Err(VaError::Unimplemented)
     })
@@ -338,6 +646,12 @@ extern "C" fn va_begin_picture(
     _context: VAContextID,
     _render_target: VASurfaceID,
 ) -> VAStatus {
+    // TODO: Players flush and simply continue with BeginPicture after a seek. Detect the
+    // IDR/keyframe boundary here, reset the DPB and the video coding state
+    // (vkCmdControlVideoCodingKHR with VK_VIDEO_CODING_CONTROL_RESET_BIT_KHR) and drop stale
+    // in-flight work, so a seek doesn't require tearing down the context.
+    // TODO: Check whether the render target is still the target of in-flight work and handle it
+    // according to DisplaySettings::surface_busy, same for vaPutImage and vaDeriveImage.
     with_driver_context(driver_context, |_driver_context| {
         Err(VaError::Unimplemented)
     })
@@ -349,12 +663,52 @@ extern "C" fn va_render_picture(
     _buffers: *mut VABufferID,
     _num_buffers: c_int,
 ) -> VAStatus {
+    // TODO: Reject buffers whose buffer_types::BufferTypeInfo::check_render fails, and keep the
+    // contents of Scope::Sequence buffers in the context for the following pictures.
+    // TODO: Clients chunk slices differently (one call per slice, or all slices at once), so
+    // collect the slice data and parameters of all calls into the context's bitstream buffer and
+    // only decode the whole picture in vaEndPicture, as advertised by VAConfigAttribDecSliceMode.
+    // TODO: For encode contexts, accept VAEncMiscParameterTypeHDR mastering display and content
+    // light level metadata and emit it as SEI (HEVC) or metadata OBUs (AV1) with the next frame,
+    // so HDR transcodes keep their metadata.
+    // TODO: For HEVC decode, pick up mastering display colour volume and content light level SEI
+    // from the slice data when clients pass it along (or from VAHdrMetaDataHDR10 buffers), and
+    // attach it to the render target for the VPP tone mapper and for export.
+    // TODO: Slice normalization, start-code scanning and header parsing can be significant at
+    // high bitrates. Hand them to a per-context worker pool so they run in parallel with the
+    // caller and the GPU, with order-preserving handoff into the submission queue.
+    // TODO: Clients may send new SPS/PPS (H.264/H.265) or sequence headers (AV1) mid-stream
+    // without recreating the context. Diff them against the parameters of the session's
+    // VkVideoSessionParametersKHR and add the changed ones with vkUpdateVideoSessionParametersKHR
+    // (bumping updateSequenceCount), rather than failing or decoding with stale parameters.
+    // parameter_sets::ParameterSetCache tells which ones changed.
+    // TODO: Reject H.264 encode pictures whose entropy_coding_mode_flag isn't allowed by the
+    // config's CONFIG_ATTRIB_ENTROPY_MODES with VA_STATUS_ERROR_INVALID_PARAMETER.
+    // TODO: Debug-only (behind a cargo feature) cross-check of the VA parameters against the
+    // submitted bitstream: parse the H.264/HEVC slice headers on the CPU and log fields that
+    // disagree with the slice and picture parameters (slice type, frame_num, POC, reference
+    // counts). Client bugs like these otherwise only show up as corruption.
     with_driver_context(driver_context, |_driver_context| {
         Err(VaError::Unimplemented)
     })
 }
 
 extern "C" fn va_end_picture(driver_context: VADriverContextP, _context: VAContextID) -> VAStatus {
+    // TODO: Browsers pass picture parameters parsed from untrusted web content. Before recording
+    // the decode, cross-check every RefPicList/ReferenceFrames entry against the DPB state: drop
+    // entries naming surfaces that aren't in the DPB or were never decoded, and log discrepancies,
+    // instead of handing invalid references to the Vulkan driver.
+    // TODO: AV1/VP9 show_existing_frame (and VP9 show_existing_frame_to_show) pictures come
+    // without tile data. Copy or alias the referenced DPB picture to the render target instead of
+    // submitting a decode, otherwise content with hidden frames stalls or shows garbage.
+    // TODO: VP9 reference scaling and AV1 superres/resized references: references may have a
+    // different size than the current frame. Use the implementation's support where it reports
+    // it, otherwise scale the references to the frame size in a compute pass first, so
+    // adaptive-resolution WebRTC streams decode correctly.
+    // TODO: For encode contexts, accept BGRX/BGRA/RGBX/RGBA source surfaces (from screen
+    // capture) and convert them to the encoder's input format with the VPP CSC shaders on the
+    // compute queue before the encode is submitted, then also advertise VA_RT_FORMAT_RGB32 in the
+    // RTFormat attribute of encode configs.
     with_driver_context(driver_context, |_driver_context| {
         Err(VaError::Unimplemented)
     })
@@ -364,6 +718,13 @@ extern "C" fn va_sync_surface(
     driver_context: VADriverContextP,
     _render_target: VASurfaceID,
 ) -> VAStatus {
+    // TODO: Optional per-picture 64-bit timestamp, passed in a driver-specific buffer type with
+    // the picture and returned here and with vaExportSurfaceHandle (through a driver-specific
+    // surface attribute), for pipelines that lose the PTS association across asynchronous decode.
+    // TODO: With checksum::enabled(), read back decoded pictures once they are done and log
+    // their checksum::PictureChecksum.
+    // TODO: Wait with watchdog::wait_for_picture, and on timeout fail the surface's context
+    // without touching any of its resources again.
     with_driver_context(driver_context, |_driver_context| {
         Err(VaError::Unimplemented)
     })
@@ -379,16 +740,56 @@ extern "C" fn va_query_surface_status(
     })
 }
 
-extern "C" fn va_query_image_formats(
+/// error_info:
+/// > upon the return, error_info will point to an array of _VASurfaceDecodeMBErrors structure,
+/// > which is allocated and filled by libVA with detailed information on the missing or error
+/// > macroblocks. The array is terminated if "status==-1" is detected.
+extern "C" fn va_query_surface_error(
     driver_context: VADriverContextP,
-    _format_list: *mut VAImageFormat, // out
-    _num_formats: *mut c_int,         // out
+    _render_target: VASurfaceID,
+    _error_status: VAStatus,
+    _error_info: *mut *mut c_void, // out
 ) -> VAStatus {
+    // TODO: When a slice references a DPB slot that was never decoded, the decode path should
+    // substitute the closest available reference (or a grey frame) instead of failing the
+    // submission, and flag the surface as corrupted so it can be reported here.
     with_driver_context(driver_context, |_driver_context| {
         Err(VaError::Unimplemented)
     })
 }
 
+extern "C" fn va_query_image_formats(
+    driver_context: VADriverContextP,
+    format_list: *mut VAImageFormat, // out
+    num_formats: *mut c_int,         // out
+) -> VAStatus {
+    if format_list.is_null() || !format_list.is_aligned() {
+        return VaError::InvalidParameter.into();
+    }
+    if num_formats.is_null() || !num_formats.is_aligned() {
+        return VaError::InvalidParameter.into();
+    }
+
+    with_driver_context(driver_context, |driver_context| {
+        let driver_data = unsafe { DriverData::from_ptr(driver_context.pDriverData)? };
+
+        let formats = if driver_data.quirks.nv12_only {
+            &image_formats::IMAGE_FORMATS[..1]
+        } else {
+            &image_formats::IMAGE_FORMATS[..]
+        };
+        // SAFETY: Null/unaligned checks are done above. Docs state:
+        // > The caller must provide a "format_list" array that can hold at least
+        // > vaMaxNumImageFormats() entries.
+        unsafe {
+            write_list(format_list, driver_context.max_image_formats, formats)?;
+            *num_formats = formats.len() as c_int;
+        }
+
+        Ok(())
+    })
+}
+
 extern "C" fn va_create_image(
     driver_context: VADriverContextP,
     _format: *mut VAImageFormat,
@@ -401,11 +802,57 @@ extern "C" fn va_create_image(
     })
 }
 
+/// mem_type:
+/// > memory type to export to
+///
+/// flags:
+/// > combination of flags to apply
+///
+/// descriptor:
+/// > pointer to the descriptor structure to fill with the handle details. The type of this
+/// > structure depends on the value of mem_type.
+extern "C" fn va_export_surface_handle(
+    driver_context: VADriverContextP,
+    surface_id: VASurfaceID,
+    mem_type: u32,
+    flags: u32,
+    descriptor: *mut c_void, // out
+) -> VAStatus {
+    let descriptor: *mut VADRMPRIMESurfaceDescriptor = descriptor.cast();
+    if descriptor.is_null() || !descriptor.is_aligned() {
+        return VaError::InvalidParameter.into();
+    }
+
+    with_driver_context(driver_context, |_driver_context| {
+        if mem_type != va_backend_sys::VA_SURFACE_ATTRIB_MEM_TYPE_DRM_PRIME_2 {
+            return Err(VaError::UnsupportedMemoryType
+                .context(format!("can't export to memory type {mem_type:#x}")));
+        }
+        let _layers = export::ExportLayers::from_flags(flags)?;
+        let _access = export::ExportAccess::from_flags(flags)?;
+
+        // TODO: Look up the surface and describe its image with export::ExportedImage.
+        // TODO: Split decode/render processes (Chromium's GPU process) also need the surface's
+        // completion as a synchronization primitive. Export the timeline semaphore of the last
+        // write as an opaque fd (VK_KHR_external_semaphore_fd, or a sync file for the binary
+        // case) through a driver-specific memory type/flag here, and accept an imported wait
+        // semaphore on contexts through a driver-specific buffer type passed to vaRenderPicture.
+        Err(VaError::InvalidSurface.object(surface_id))
+    })
+}
+
 extern "C" fn va_derive_image(
     driver_context: VADriverContextP,
     _surface: VASurfaceID,
     _image: *mut VAImage, // out
 ) -> VAStatus {
+    // TODO: Surfaces with optimal tiling can't be derived. For those, vaGetImage could detile with
+    // a single compute dispatch writing a linear copy into a host-visible buffer, which is faster
+    // than transfer queue copies on bandwidth-starved iGPUs.
+    // TODO: Quality analysis tools (PSNR/SSIM loops) and ML rate controllers need the encoder's
+    // reconstructed pictures. Behind a driver-specific config attribute, create encode DPB images
+    // with TRANSFER_SRC usage and allow deriving (or exporting as dma-buf) the reconstructed
+    // surface of a finished picture.
     with_driver_context(driver_context, |_driver_context| {
         Err(VaError::Unimplemented)
     })
@@ -445,6 +892,12 @@ extern "C" fn va_get_image(
     _height: c_uint,
     _image: VAImageID,
 ) -> VAStatus {
+    // TODO: Clients often only read back a small rectangle (e.g. an OSD region). Restrict the copy
+    // regions to x/y/width/height, scaled per plane for subsampled chroma, and only stage that
+    // much, instead of copying the whole surface. Same for the source rectangle in vaPutImage.
+    // TODO: Reading back interlaced surfaces gives combed images in screenshot paths. Optionally
+    // weave or bob the fields, selected by a flag, using the VPP deinterlacing pass rather than a
+    // separate implementation.
     with_driver_context(driver_context, |_driver_context| {
         Err(VaError::Unimplemented)
     })
@@ -470,12 +923,36 @@ extern "C" fn va_put_image(
 
 extern "C" fn va_query_subpicture_formats(
     driver_context: VADriverContextP,
-    _format_list: *mut VAImageFormat, // out
-    _flags: *mut c_uint,              // out
-    _num_formats: *mut c_uint,        // out
+    format_list: *mut VAImageFormat, // out
+    flags: *mut c_uint,              // out
+    num_formats: *mut c_uint,        // out
 ) -> VAStatus {
-    with_driver_context(driver_context, |_driver_context| {
-        Err(VaError::Unimplemented)
+    if format_list.is_null() || !format_list.is_aligned() {
+        return VaError::InvalidParameter.into();
+    }
+    if flags.is_null() || !flags.is_aligned() {
+        return VaError::InvalidParameter.into();
+    }
+    if num_formats.is_null() || !num_formats.is_aligned() {
+        return VaError::InvalidParameter.into();
+    }
+
+    with_driver_context(driver_context, |driver_context| {
+        let formats = image_formats::SUBPICTURE_FORMATS.map(|(format, _)| format);
+        let format_flags = image_formats::SUBPICTURE_FORMATS.map(|(_, flags)| flags);
+        let capacity = driver_context.max_subpic_formats;
+
+        // SAFETY: Null/unaligned checks are done above. Docs state:
+        // > The caller must provide a "format_list" array that can hold at
+        // > least vaMaxNumSubpictureFormats() entries. The flags arrary holds the flag
+        // > for each format to indicate additional capabilities for that format.
+        unsafe {
+            write_list(format_list, capacity, &formats)?;
+            write_list(flags, capacity, &format_flags)?;
+            *num_formats = formats.len() as c_uint;
+        }
+
+        Ok(())
     })
 }
 
@@ -554,6 +1031,9 @@ extern "C" fn va_associate_subpicture(
     _dest_height: c_ushort,
     _flags: c_uint,
 ) -> VAStatus {
+    // TODO: Once subpictures are blended, compile the chroma-key min/max/mask and the global alpha
+    // into the blend shader as specialization constants, selected per association by
+    // VA_SUBPICTURE_CHROMA_KEYING and VA_SUBPICTURE_GLOBAL_ALPHA (DVD menus rely on both).
     with_driver_context(driver_context, |_driver_context| {
         Err(VaError::Unimplemented)
     })
@@ -572,31 +1052,107 @@ extern "C" fn va_deassociate_subpicture(
 
 extern "C" fn va_query_display_attributes(
     driver_context: VADriverContextP,
-    _attr_list: *mut VADisplayAttribute, // out
-    _num_attributes: *mut c_int,         // out
+    attr_list: *mut VADisplayAttribute, // out
+    num_attributes: *mut c_int,         // out
 ) -> VAStatus {
-    with_driver_context(driver_context, |_driver_context| {
-        Err(VaError::Unimplemented)
+    if attr_list.is_null() || !attr_list.is_aligned() {
+        return VaError::InvalidParameter.into();
+    }
+    if num_attributes.is_null() || !num_attributes.is_aligned() {
+        return VaError::InvalidParameter.into();
+    }
+
+    with_driver_context(driver_context, |driver_context| {
+        let driver_data = unsafe { DriverData::from_ptr(driver_context.pDriverData)? };
+
+        let attributes = DisplayAttrib::ALL
+            .map(|attrib| attrib.describe(&driver_data.settings, driver_data.rate_stats.as_ref()));
+
+        // SAFETY: Null/unaligned checks are done above. Docs state:
+        // > The caller must provide a "attr_list" array that can hold at least
+        // > vaMaxNumDisplayAttributes() entries.
+        unsafe {
+            write_list(
+                attr_list,
+                driver_context.max_display_attributes,
+                &attributes,
+            )?;
+            *num_attributes = attributes.len() as c_int;
+        }
+
+        Ok(())
     })
 }
 
 extern "C" fn va_get_display_attributes(
     driver_context: VADriverContextP,
-    _attr_list: *mut VADisplayAttribute, // in/out
-    _num_attributes: c_int,
+    attr_list: *mut VADisplayAttribute, // in/out
+    num_attributes: c_int,
 ) -> VAStatus {
-    with_driver_context(driver_context, |_driver_context| {
-        Err(VaError::Unimplemented)
+    if attr_list.is_null() || !attr_list.is_aligned() || num_attributes < 0 {
+        return VaError::InvalidParameter.into();
+    }
+
+    with_driver_context(driver_context, |driver_context| {
+        let driver_data = unsafe { DriverData::from_ptr(driver_context.pDriverData)? };
+
+        // SAFETY: Null/unaligned checks are done above, the caller provides `num_attributes`
+        // entries.
+        let attributes =
+            unsafe { std::slice::from_raw_parts_mut(attr_list, num_attributes as usize) };
+
+        for attribute in attributes {
+            match DisplayAttrib::from_va(attribute.type_) {
+                Some(attrib) => {
+                    *attribute =
+                        attrib.describe(&driver_data.settings, driver_data.rate_stats.as_ref())
+                }
+                // > If the attribute is not gettable, the flags field is set to
+                // > VA_DISPLAY_ATTRIB_NOT_SUPPORTED.
+                None => attribute.flags = va_backend_sys::VA_DISPLAY_ATTRIB_NOT_SUPPORTED,
+            }
+        }
+
+        Ok(())
     })
 }
 
 extern "C" fn va_set_display_attributes(
     driver_context: VADriverContextP,
-    _attr_list: *mut VADisplayAttribute,
-    _num_attributes: c_int,
+    attr_list: *mut VADisplayAttribute,
+    num_attributes: c_int,
 ) -> VAStatus {
-    with_driver_context(driver_context, |_driver_context| {
-        Err(VaError::Unimplemented)
+    if attr_list.is_null() || !attr_list.is_aligned() || num_attributes < 0 {
+        return VaError::InvalidParameter.into();
+    }
+
+    with_driver_context(driver_context, |driver_context| {
+        let driver_data = unsafe { DriverData::from_ptr(driver_context.pDriverData)? };
+
+        // SAFETY: Null/unaligned checks are done above, the caller provides `num_attributes`
+        // entries.
+        let attributes = unsafe { std::slice::from_raw_parts(attr_list, num_attributes as usize) };
+
+        // Validate everything first so a rejected list doesn't leave the settings half-applied.
+        let mut updates = Vec::with_capacity(attributes.len());
+        for attribute in attributes {
+            // > If the attribute is not settable or the value is out of range, the function
+            // > returns VA_STATUS_ERROR_ATTR_NOT_SUPPORTED
+            let attrib =
+                DisplayAttrib::from_va(attribute.type_).ok_or(VaError::AttrNotSupported)?;
+            let (min_value, max_value) = attrib.range();
+            if !attrib.settable() || !(min_value..=max_value).contains(&attribute.value) {
+                return Err(VaError::AttrNotSupported);
+            }
+            updates.push((attrib, attribute.value));
+        }
+
+        for (attrib, value) in updates {
+            debug!("Setting display attribute {attrib:?} to {value}");
+            attrib.set(&mut driver_data.settings, value);
+        }
+
+        Ok(())
     })
 }
 
@@ -623,8 +1179,8 @@ fn fill_vtable(vtable: &mut VADriverVTable) {
         vaEndPicture: Some(va_end_picture),
         vaSyncSurface: Some(va_sync_surface),
         vaQuerySurfaceStatus: Some(va_query_surface_status),
-        vaQuerySurfaceError: None, // TODO:
-        vaPutSurface: None,        // TODO:
+        vaQuerySurfaceError: Some(va_query_surface_error),
+        vaPutSurface: None, // TODO:
         vaQueryImageFormats: Some(va_query_image_formats),
         vaCreateImage: Some(va_create_image),
         vaDeriveImage: Some(va_derive_image),
@@ -643,31 +1199,33 @@ fn fill_vtable(vtable: &mut VADriverVTable) {
         vaQueryDisplayAttributes: Some(va_query_display_attributes),
         vaGetDisplayAttributes: Some(va_get_display_attributes),
         vaSetDisplayAttributes: Some(va_set_display_attributes),
-        vaBufferInfo: None,             // TODO:
-        vaLockSurface: None,            // TODO:
-        vaUnlockSurface: None,          // TODO:
-        vaGetSurfaceAttributes: None,   // TODO:
-        vaCreateSurfaces2: None,        // TODO:
+        vaBufferInfo: None,           // TODO:
+        vaLockSurface: None,          // TODO:
+        vaUnlockSurface: None,        // TODO:
+        vaGetSurfaceAttributes: None, // TODO:
+        vaCreateSurfaces2: Some(va_create_surfaces2),
         vaQuerySurfaceAttributes: None, // TODO:
-        vaAcquireBufferHandle: None,    // TODO:
-        vaReleaseBufferHandle: None,    // TODO:
-        vaCreateMFContext: None,        // TODO:
-        vaMFAddContext: None,           // TODO:
-        vaMFReleaseContext: None,       // TODO:
-        vaMFSubmit: None,               // TODO:
-        vaCreateBuffer2: None,          // TODO:
-        vaQueryProcessingRate: None,    // TODO:
-        vaExportSurfaceHandle: None,    // TODO:
-        vaSyncSurface2: None,           // TODO:
-        vaSyncBuffer: None,             // TODO:
-        vaCopy: None,                   // TODO:
-        vaMapBuffer2: None,             // TODO:
+        // TODO: Export image buffers as dma-buf (VA_SURFACE_ATTRIB_MEM_TYPE_DRM_PRIME or
+        // KERNEL_DRM) for EGL_EXT_image_dma_buf_import, once buffers are backed by device memory.
+        // As linear host-visible allocations, they'd always have DRM_FORMAT_MOD_LINEAR, with the
+        // DRM fourcc matching the VA fourcc of the image (DRM_FORMAT_NV12, DRM_FORMAT_P010).
+        vaAcquireBufferHandle: None, // TODO:
+        vaReleaseBufferHandle: None, // TODO:
+        vaCreateMFContext: None,     // TODO:
+        vaMFAddContext: None,        // TODO:
+        vaMFReleaseContext: None,    // TODO:
+        vaMFSubmit: None,            // TODO:
+        vaCreateBuffer2: None,       // TODO:
+        vaQueryProcessingRate: None, // TODO:
+        vaExportSurfaceHandle: Some(va_export_surface_handle),
+        vaSyncSurface2: None, // TODO:
+        vaSyncBuffer: None,   // TODO:
+        vaCopy: None,         // TODO:
+        vaMapBuffer2: None,   // TODO:
         reserved: [0 as c_ulong; _],
     };
 }
 
-const VENDOR: &CStr = c"va_vulkan_video";
-
 unsafe extern "system" fn vulkan_debug_callback(
     message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
     message_type: vk::DebugUtilsMessageTypeFlagsEXT,
@@ -705,6 +1263,14 @@ unsafe extern "system" fn vulkan_debug_callback(
     vk::FALSE
 }
 
+/// Whether the physical device is the DRM device the display was opened on, either through its
+/// primary node (X11/Wayland, vaGetDisplayDRM on /dev/dri/cardN) or its render node.
+///
+/// Headless transcode servers open displays with vaGetDisplayDRM on /dev/dri/renderD128 and have
+/// no display server at all. Nothing in the driver may depend on one: the render node is all we
+/// need to find the physical device, and all work goes through Vulkan.
+// TODO: Integration test running a decode and an encode through vaGetDisplayDRM on a render node
+// with no DISPLAY/WAYLAND_DISPLAY set, once the decode and encode paths exist.
 fn vulkan_device_is_same_as_drm(
     drm_properties: &vk::PhysicalDeviceDrmPropertiesEXT,
     drm_device_id: DeviceId,
@@ -732,7 +1298,41 @@ enum Operation {
     Encode,
 }
 
-#[derive(Debug, Default)]
+/// Codec of the VA profile `profile`, if it is one we know.
+fn va_profile_codec(profile: VAProfile) -> Option<Codec> {
+    match profile {
+        va_backend_sys::VAProfile_VAProfileH264Baseline
+        | va_backend_sys::VAProfile_VAProfileH264ConstrainedBaseline
+        | va_backend_sys::VAProfile_VAProfileH264Main
+        | va_backend_sys::VAProfile_VAProfileH264High => Some(Codec::H264),
+        va_backend_sys::VAProfile_VAProfileHEVCMain
+        | va_backend_sys::VAProfile_VAProfileHEVCMain10 => Some(Codec::H265),
+        va_backend_sys::VAProfile_VAProfileVP9Profile0
+        | va_backend_sys::VAProfile_VAProfileVP9Profile1
+        | va_backend_sys::VAProfile_VAProfileVP9Profile2
+        | va_backend_sys::VAProfile_VAProfileVP9Profile3 => Some(Codec::Vp9),
+        va_backend_sys::VAProfile_VAProfileAV1Profile0
+        | va_backend_sys::VAProfile_VAProfileAV1Profile1 => Some(Codec::Av1),
+        _ => None,
+    }
+}
+
+/// Whether support for `codec`/`operation` is enabled through the cargo features.
+const fn codec_enabled(codec: Codec, operation: Operation) -> bool {
+    let codec = match codec {
+        Codec::H264 => cfg!(feature = "h264"),
+        Codec::H265 => cfg!(feature = "h265"),
+        Codec::Vp9 => cfg!(feature = "vp9"),
+        Codec::Av1 => cfg!(feature = "av1"),
+    };
+    let operation = match operation {
+        Operation::Decode => true,
+        Operation::Encode => cfg!(feature = "encode"),
+    };
+    codec && operation
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 struct SupportedCodecs {
     // TODO: bitflags
     h264_decode: bool,
@@ -744,21 +1344,174 @@ struct SupportedCodecs {
     av1_encode: bool,
 }
 
+impl SupportedCodecs {
+    /// Clears the codecs that the selected queue families can't run.
+    ///
+    /// Virtualized GPUs (SR-IOV, vGPU) may expose the codec extensions of the physical GPU while
+    /// the queue families of their slice lack some (or all) of the operations, so the extensions
+    /// alone overstate what can be used.
+    fn restrict_to_queue_families(
+        &mut self,
+        decode_operations: vk::VideoCodecOperationFlagsKHR,
+        encode_operations: vk::VideoCodecOperationFlagsKHR,
+    ) {
+        let before = self.clone();
+        self.h264_decode &=
+            decode_operations.contains(vk::VideoCodecOperationFlagsKHR::DECODE_H264);
+        self.h265_decode &=
+            decode_operations.contains(vk::VideoCodecOperationFlagsKHR::DECODE_H265);
+        self.av1_decode &= decode_operations.contains(vk::VideoCodecOperationFlagsKHR::DECODE_AV1);
+        self.h264_encode &=
+            encode_operations.contains(vk::VideoCodecOperationFlagsKHR::ENCODE_H264);
+        self.h265_encode &=
+            encode_operations.contains(vk::VideoCodecOperationFlagsKHR::ENCODE_H265);
+        if *self != before {
+            warn!(
+                "Queue families don't support all codecs of the device extensions (virtualized \
+                 GPU?), only advertising {self:?}"
+            );
+        }
+    }
+
+    fn supports(&self, codec: Codec, operation: Operation) -> bool {
+        match (codec, operation) {
+            (Codec::H264, Operation::Decode) => self.h264_decode,
+            (Codec::H265, Operation::Decode) => self.h265_decode,
+            (Codec::Vp9, Operation::Decode) => self.vp9_decode,
+            (Codec::Av1, Operation::Decode) => self.av1_decode,
+            (Codec::H264, Operation::Encode) => self.h264_encode,
+            (Codec::H265, Operation::Encode) => self.h265_encode,
+            (Codec::Av1, Operation::Encode) => self.av1_encode,
+            (Codec::Vp9, Operation::Encode) => false,
+        }
+    }
+
+    fn any(&self) -> bool {
+        self.h264_decode
+            || self.h265_decode
+            || self.vp9_decode
+            || self.av1_decode
+            || self.h264_encode
+            || self.h265_encode
+            || self.av1_encode
+    }
+}
+
+#[derive(Clone)]
 struct CodecQueueFamilyInfo {
     index: usize,
     count: u32,
     operations: vk::VideoCodecOperationFlagsKHR,
     query_result_status_support: bool,
+    /// Global priorities queues of this family can be created with. Empty if neither
+    /// VK_KHR_global_priority nor VK_EXT_global_priority is supported.
+    global_priorities: Vec<vk::QueueGlobalPriorityKHR>,
+}
+
+impl CodecQueueFamilyInfo {
+    /// Global priority to create queues of this family with, or `None` to use the default
+    /// (medium) priority.
+    ///
+    /// High priority lets e.g. a videoconferencing encode preempt a background transcode on the
+    /// same GPU. It usually requires privileges (e.g. CAP_SYS_NICE), so creating the queue may
+    /// fail with VK_ERROR_NOT_PERMITTED_KHR, in which case it should be retried without a
+    /// priority.
+    #[allow(dead_code)] // Used once queues are created
+    fn global_priority(&self, priority: ContextPriority) -> Option<vk::QueueGlobalPriorityKHR> {
+        let global_priority = match priority {
+            ContextPriority::Low => vk::QueueGlobalPriorityKHR::LOW,
+            ContextPriority::Normal => return None,
+            ContextPriority::High => vk::QueueGlobalPriorityKHR::HIGH,
+        };
+
+        if self.global_priorities.contains(&global_priority) {
+            Some(global_priority)
+        } else {
+            warn!(
+                "Queue family {} doesn't support global priority {global_priority:?}, \
+                using the default priority",
+                self.index
+            );
+            None
+        }
+    }
 }
 
+/// Which of the global priority extensions a physical device supports.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum GlobalPrioritySupport {
+    None,
+    Ext,
+    Khr,
+}
+
+const GLOBAL_PRIORITIES: [vk::QueueGlobalPriorityKHR; 4] = [
+    vk::QueueGlobalPriorityKHR::LOW,
+    vk::QueueGlobalPriorityKHR::MEDIUM,
+    vk::QueueGlobalPriorityKHR::HIGH,
+    vk::QueueGlobalPriorityKHR::REALTIME,
+];
+
+/// Vulkan state of a display.
+///
+/// The instance and the probed physical device are shared with the other displays of the
+/// process. Logical devices and queues will be per display, so that displays don't contend on the
+/// same queues.
 struct VulkanData {
-    entry: ash::Entry,
+    /// Keeps the instance (and the probed devices) alive while the display uses them.
+    #[allow(dead_code)] // Only held, `instance` is used instead
+    shared: Arc<SharedInstance>,
     instance: ash::Instance,
-    debug_utils_loader: ext::debug_utils::Instance,
-    debug_call_back: vk::DebugUtilsMessengerEXT,
     physical_device: vk::PhysicalDevice,
     supported_codecs: SupportedCodecs,
     decode_queue_family: CodecQueueFamilyInfo,
+    encode_queue_family: Option<CodecQueueFamilyInfo>,
+    /// Queue family for VPP. Its work is chained to the video queues with semaphores, and images
+    /// are transferred between the families, see [`barriers::ImageTracker`].
+    #[allow(dead_code)] // Used once VPP is implemented
+    compute_queue_family: Option<usize>,
+    decode_capabilities: Vec<(VAProfile, VideoCapabilities)>,
+    encode_capabilities: Vec<(VAProfile, EncodeCapabilities)>,
+    workarounds: Workarounds,
+    /// Device memory allocated by the display. Shared by its contexts, which allocate from
+    /// different threads.
+    memory_budget: Mutex<MemoryBudget>,
+}
+
+impl VulkanData {
+    /// Decode capabilities for `profile`, or `None` if it can't be decoded.
+    fn decode_capabilities(&self, profile: VAProfile) -> Option<&VideoCapabilities> {
+        self.decode_capabilities
+            .iter()
+            .find(|(p, _)| *p == profile)
+            .map(|(_, capabilities)| capabilities)
+    }
+
+    /// Encode capabilities for `profile`, or `None` if it can't be encoded.
+    fn encode_capabilities(&self, profile: VAProfile) -> Option<&EncodeCapabilities> {
+        self.encode_capabilities
+            .iter()
+            .find(|(p, _)| *p == profile)
+            .map(|(_, capabilities)| capabilities)
+    }
+
+    /// Image usage for surfaces.
+    ///
+    /// When both a decode and an encode queue are available, surfaces are usable as decode output
+    /// and encode input at the same time, so decode->encode transcodes can pass surfaces directly
+    /// between contexts without copies.
+    // TODO: Also require that the surface format is supported by both the decode output and the
+    // encode input of the respective profiles.
+    #[allow(dead_code)] // Used once surfaces are allocated
+    fn surface_image_usage(&self) -> vk::ImageUsageFlags {
+        let mut usage = vk::ImageUsageFlags::TRANSFER_SRC
+            | vk::ImageUsageFlags::TRANSFER_DST
+            | vk::ImageUsageFlags::VIDEO_DECODE_DST_KHR;
+        if self.encode_queue_family.is_some() {
+            usage |= vk::ImageUsageFlags::VIDEO_ENCODE_SRC_KHR;
+        }
+        usage
+    }
 }
 
 // NOTE: Must be sorted by the extension name for binary search
@@ -767,52 +1520,36 @@ const CODEC_EXTENSIONS: [(&CStr, Codec, Operation); 5] = [
     (khr::video_decode_h264::NAME, Codec::H264, Operation::Decode),
     (khr::video_decode_h265::NAME, Codec::H265, Operation::Decode),
     // (khr::video_decode_vp9::NAME, Codec::Vp9, Operation::Decode),
+    // TODO: VK_KHR_video_encode_av1 needs a newer ash than 0.38 (Vulkan 1.3.281). Once it's
+    // available, honor the tile rows/columns of VAEncPictureParameterBufferAV1, including
+    // non-uniform splits, validated against VkVideoEncodeAV1CapabilitiesKHR::maxTiles and the
+    // superblock size, so AV1 streaming can use many tiles for parallel decoding.
     // (khr::video_encode_av1::NAME, Codec::Av1, Operation::Encode),
     (khr::video_encode_h264::NAME, Codec::H264, Operation::Encode),
     (khr::video_encode_h265::NAME, Codec::H265, Operation::Encode),
 ];
 
-fn init_vulkan(device_id: DeviceId) -> VkResult<VulkanData> {
-    let entry = ash::Entry::linked();
-
-    let app_info = vk::ApplicationInfo::default()
-        .application_name(c"Vulkan Video VA-API Driver")
-        .application_version(0)
-        .engine_name(VENDOR)
-        .engine_version(0)
-        .api_version(vk::API_VERSION_1_3);
-
-    let layer_names = vec![c"VK_LAYER_KHRONOS_validation".as_ptr()];
-    let extension_names = vec![ext::debug_utils::NAME.as_ptr()];
-
-    let mut debug_info = vk::DebugUtilsMessengerCreateInfoEXT::default()
-        .message_severity(
-            vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE
-                | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
-                | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
-                | vk::DebugUtilsMessageSeverityFlagsEXT::INFO,
-        )
-        .message_type(
-            vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
-                | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
-                | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
-        )
-        .pfn_user_callback(Some(vulkan_debug_callback));
-
-    let create_info = vk::InstanceCreateInfo::default()
-        .application_info(&app_info)
-        .enabled_layer_names(&layer_names)
-        .enabled_extension_names(&extension_names)
-        .push_next(&mut debug_info);
-
-    let instance = unsafe { entry.create_instance(&create_info, None)? };
-    debug!("Vulkan instance created successfully");
-
-    let debug_utils_loader = ext::debug_utils::Instance::new(&entry, &instance);
-    let debug_call_back =
-        unsafe { debug_utils_loader.create_debug_utils_messenger(&debug_info, None)? };
-    debug!("Debug utils messenger created successfully");
+/// The physical device matching a DRM device, along with the results of probing it.
+#[derive(Clone)]
+struct PhysicalDeviceInfo {
+    physical_device: vk::PhysicalDevice,
+    supported_codecs: SupportedCodecs,
+    decode_queue_family: CodecQueueFamilyInfo,
+    encode_queue_family: Option<CodecQueueFamilyInfo>,
+    compute_queue_family: Option<usize>,
+    memory_budget_supported: bool,
+    /// Decode capabilities of the supported VA profiles.
+    decode_capabilities: Vec<(VAProfile, VideoCapabilities)>,
+    /// Encode capabilities of the supported VA profiles.
+    encode_capabilities: Vec<(VAProfile, EncodeCapabilities)>,
+    workarounds: Workarounds,
+}
 
+fn probe_physical_device(
+    entry: &ash::Entry,
+    instance: &ash::Instance,
+    device_id: DeviceId,
+) -> VkResult<PhysicalDeviceInfo> {
     let physical_devices = unsafe { instance.enumerate_physical_devices()? };
     debug!("Found {} physical devices", physical_devices.len());
 
@@ -821,8 +1558,14 @@ fn init_vulkan(device_id: DeviceId) -> VkResult<VulkanData> {
     // https://wgpu.rs/doc/wgpu_hal/vulkan/struct.Instance.html#method.create_surface_from_drm
 
     let mut physical_device = None;
-
-    // let video_queue_loader = khr::video_queue::Instance::new(&entry, &instance);
+    // On ARM SoCs, the display is usually opened on the display controller (e.g. rockchip-drm),
+    // while the GPU doing the video work is a separate, render-only device without a primary
+    // node. If nothing matches the DRM device, such a device is used if it's the only candidate.
+    let mut render_only_devices = Vec::new();
+    // In containers, the DRM nodes may be bind-mounted with different device numbers, or device
+    // namespaces may hide them from Vulkan. The PCI address from sysfs still identifies the GPU.
+    let drm_pci_address = PciAddress::of_drm_device(device_id);
+    let mut pci_match = None;
 
     for device in physical_devices {
         let mut drm_props = vk::PhysicalDeviceDrmPropertiesEXT::default();
@@ -844,15 +1587,41 @@ fn init_vulkan(device_id: DeviceId) -> VkResult<VulkanData> {
         let extensions = unsafe { instance.enumerate_device_extension_properties(device)? };
 
         let mut supported_codecs = SupportedCodecs::default();
+        let mut memory_budget_supported = false;
+        let mut global_priority = GlobalPrioritySupport::None;
+        let mut pci_bus_info_supported = false;
         for ext in extensions {
             let Ok(ext_name) = ext.extension_name_as_c_str() else {
                 trace!("Invalid extension name: {:?}", ext.extension_name);
                 continue;
             };
 
+            if ext_name == ext::memory_budget::NAME {
+                memory_budget_supported = true;
+                continue;
+            }
+            if ext_name == ext::pci_bus_info::NAME {
+                pci_bus_info_supported = true;
+                continue;
+            }
+            if ext_name == khr::global_priority::NAME {
+                global_priority = GlobalPrioritySupport::Khr;
+                continue;
+            }
+            if ext_name == ext::global_priority::NAME
+                && global_priority == GlobalPrioritySupport::None
+            {
+                global_priority = GlobalPrioritySupport::Ext;
+                continue;
+            }
+
             let codec_ext = CODEC_EXTENSIONS.binary_search_by_key(&ext_name, |(name, _, _)| *name);
             if let Ok(i) = codec_ext {
                 let (_, codec, operation) = CODEC_EXTENSIONS[i];
+                if !codec_enabled(codec, operation) {
+                    debug!("Ignoring {ext_name:?}, disabled at build time");
+                    continue;
+                }
                 match (codec, operation) {
                     (Codec::Av1, Operation::Decode) => supported_codecs.av1_decode = true,
                     (Codec::Av1, Operation::Encode) => supported_codecs.av1_encode = true,
@@ -877,17 +1646,80 @@ fn init_vulkan(device_id: DeviceId) -> VkResult<VulkanData> {
                 device_id.0,
                 device_id.1
             );
-            physical_device = Some((device, supported_codecs));
+            physical_device = Some((
+                device,
+                supported_codecs,
+                memory_budget_supported,
+                global_priority,
+            ));
             break;
         }
+
+        if let Some(drm_pci_address) = drm_pci_address
+            && pci_bus_info_supported
+            && pci_match.is_none()
+        {
+            let mut pci_props = vk::PhysicalDevicePCIBusInfoPropertiesEXT::default();
+            let mut properties2 =
+                vk::PhysicalDeviceProperties2KHR::default().push_next(&mut pci_props);
+            unsafe {
+                instance.get_physical_device_properties2(device, &mut properties2);
+            }
+            let address = PciAddress {
+                domain: pci_props.pci_domain,
+                bus: pci_props.pci_bus,
+                device: pci_props.pci_device,
+                function: pci_props.pci_function,
+            };
+            if address == drm_pci_address {
+                pci_match = Some((
+                    device,
+                    supported_codecs.clone(),
+                    memory_budget_supported,
+                    global_priority,
+                ));
+            }
+        }
+
+        if drm_props.has_primary == vk::FALSE && supported_codecs.any() {
+            render_only_devices.push((
+                device,
+                supported_codecs,
+                memory_budget_supported,
+                global_priority,
+            ));
+        }
     }
 
-    let Some((physical_device, supported_codecs)) = physical_device else {
+    if physical_device.is_none()
+        && pci_match.is_some()
+        && let Some(address) = drm_pci_address
+    {
+        warn!(
+            "No physical device matches the DRM device ID {}/{}, using the one at PCI address \
+             {address} instead",
+            device_id.0, device_id.1,
+        );
+        physical_device = pci_match;
+    }
+    if physical_device.is_none() && render_only_devices.len() == 1 {
+        warn!(
+            "No physical device matches the DRM device ID {}/{}, using the only render-only \
+             device with video support",
+            device_id.0, device_id.1
+        );
+        physical_device = render_only_devices.pop();
+    }
+
+    let Some((physical_device, mut supported_codecs, memory_budget_supported, global_priority)) =
+        physical_device
+    else {
         error!(
             "No suitable physical device found matching the DRM device ID {}/{}",
             device_id.0, device_id.1
         );
-        return Err(vk::Result::ERROR_INITIALIZATION_FAILED);
+        // Not ERROR_INITIALIZATION_FAILED, which is retried
+        return Err(vk::Result::ERROR_FEATURE_NOT_PRESENT);
     };
 
     let queue_family_properties_len =
@@ -898,14 +1730,22 @@ fn init_vulkan(device_id: DeviceId) -> VkResult<VulkanData> {
         vec![vk::QueueFamilyVideoPropertiesKHR::default(); queue_family_properties_len];
     let mut queue_family_query_result_status_properties =
         vec![vk::QueueFamilyQueryResultStatusPropertiesKHR::default(); queue_family_properties_len];
+    let mut queue_family_global_priority_properties =
+        vec![vk::QueueFamilyGlobalPriorityPropertiesKHR::default(); queue_family_properties_len];
 
     let mut queue_family_properties = queue_family_video_properties
         .iter_mut()
         .zip(queue_family_query_result_status_properties.iter_mut())
-        .map(|(qfvp, qfrsp)| {
-            vk::QueueFamilyProperties2KHR::default()
+        .zip(queue_family_global_priority_properties.iter_mut())
+        .map(|((qfvp, qfrsp), qfgpp)| {
+            let qfp = vk::QueueFamilyProperties2KHR::default()
                 .push_next(qfvp)
-                .push_next(qfrsp)
+                .push_next(qfrsp);
+            if global_priority == GlobalPrioritySupport::Khr {
+                qfp.push_next(qfgpp)
+            } else {
+                qfp
+            }
         })
         .collect::<Vec<_>>();
 
@@ -917,7 +1757,7 @@ fn init_vulkan(device_id: DeviceId) -> VkResult<VulkanData> {
     };
 
     // Extract the inner `vk::QueueFamilyProperties` structs. This avoids issue with the mutable
-    // borrow the outer struct holds on the other two structs via pNext.
+    // borrow the outer struct holds on the other structs via pNext.
     let queue_family_properties = queue_family_properties
         .into_iter()
         .map(|qfp| qfp.queue_family_properties)
@@ -925,6 +1765,8 @@ fn init_vulkan(device_id: DeviceId) -> VkResult<VulkanData> {
 
     // TODO: Improve selection logic, support multiple queue families, etc.
     let mut video_decode_qf = None;
+    let mut video_encode_qf = None;
+    let mut compute_qf: Option<(usize, bool)> = None;
 
     for i in 0..queue_family_properties.len() {
         let qfp = &queue_family_properties[i];
@@ -932,19 +1774,35 @@ fn init_vulkan(device_id: DeviceId) -> VkResult<VulkanData> {
         let qfrsp = &queue_family_query_result_status_properties[i];
 
         let query_result_status_support = qfrsp.query_result_status_support == vk::TRUE;
+        let global_priorities = match global_priority {
+            GlobalPrioritySupport::None => Vec::new(),
+            // Without VK_KHR_global_priority, the supported priorities can't be queried. Queue
+            // creation fails with VK_ERROR_NOT_PERMITTED_KHR if a priority isn't allowed.
+            GlobalPrioritySupport::Ext => GLOBAL_PRIORITIES.to_vec(),
+            GlobalPrioritySupport::Khr => {
+                let qfgpp = &queue_family_global_priority_properties[i];
+                qfgpp.priorities[..qfgpp.priority_count as usize].to_vec()
+            }
+        };
 
         debug!(
             "Queue family {i}: \
             flags={:?}, count={}, timestamp_valid_bits={}, \
-            video_codec_operations={:?}, query_result_status_support={:?}",
+            video_codec_operations={:?}, query_result_status_support={:?}, \
+            global_priorities={:?}",
             qfp.queue_flags,
             qfp.queue_count,
             qfp.timestamp_valid_bits,
             qfvp.video_codec_operations,
             query_result_status_support,
+            global_priorities,
         );
 
-        if qfp.queue_count > 0
+        // Virtualized GPUs have been seen to report video queue families with a queue count of
+        // zero, or without any codec operations, for the engines not assigned to the guest.
+        let has_video_queues = qfp.queue_count > 0 && !qfvp.video_codec_operations.is_empty();
+
+        if has_video_queues
             && qfp
                 .queue_flags
                 .contains(vk::QueueFlags::VIDEO_DECODE_KHR | vk::QueueFlags::TRANSFER)
@@ -954,13 +1812,36 @@ fn init_vulkan(device_id: DeviceId) -> VkResult<VulkanData> {
                 count: qfp.queue_count,
                 operations: qfvp.video_codec_operations,
                 query_result_status_support,
+                global_priorities: global_priorities.clone(),
+            });
+        }
+
+        if has_video_queues && qfp.queue_flags.contains(vk::QueueFlags::VIDEO_ENCODE_KHR) {
+            video_encode_qf = Some(CodecQueueFamilyInfo {
+                index: i,
+                count: qfp.queue_count,
+                operations: qfvp.video_codec_operations,
+                query_result_status_support,
+                global_priorities: global_priorities.clone(),
             });
         }
+
+        // Prefer a compute-only family, which is backed by separate hardware on most GPUs and
+        // therefore runs VPP asynchronously to both the video and the graphics queues.
+        if cfg!(feature = "vpp")
+            && qfp.queue_count > 0
+            && qfp.queue_flags.contains(vk::QueueFlags::COMPUTE)
+        {
+            let dedicated = !qfp.queue_flags.contains(vk::QueueFlags::GRAPHICS);
+            if compute_qf.is_none_or(|(_, other_dedicated)| dedicated && !other_dedicated) {
+                compute_qf = Some((i, dedicated));
+            }
+        }
     }
 
     let Some(decode_queue_family) = video_decode_qf else {
         error!("No suitable video decode queue family found");
-        return Err(vk::Result::ERROR_INITIALIZATION_FAILED);
+        return Err(vk::Result::ERROR_FEATURE_NOT_PRESENT);
     };
 
     info!(
@@ -968,27 +1849,117 @@ fn init_vulkan(device_id: DeviceId) -> VkResult<VulkanData> {
         decode_queue_family.index,
     );
 
-    Ok(VulkanData {
-        entry,
-        instance,
-        debug_utils_loader,
-        debug_call_back,
+    // Encode is optional, decode-only devices are still useful.
+    let encode_queue_family = video_encode_qf;
+    match &encode_queue_family {
+        Some(qf) => info!("Selected video encode queue family {}", qf.index),
+        None => info!("No video encode queue family found"),
+    }
+
+    supported_codecs.restrict_to_queue_families(
+        decode_queue_family.operations,
+        encode_queue_family
+            .as_ref()
+            .map_or(vk::VideoCodecOperationFlagsKHR::empty(), |qf| qf.operations),
+    );
+
+    let compute_queue_family = compute_qf.map(|(index, _)| index);
+    match compute_queue_family {
+        Some(index) => info!("Selected compute queue family {index}"),
+        None => info!("No compute queue family found"),
+    }
+
+    let video_queue = khr::video_queue::Instance::new(entry, instance);
+    // Only chain the codec structures of enabled codecs whose extensions the device has
+    let supported = |profile, operation| {
+        va_profile_codec(profile).is_some_and(|codec| supported_codecs.supports(codec, operation))
+    };
+    let decode_capabilities = PROFILES
+        .into_iter()
+        .filter(|&profile| supported(profile, Operation::Decode))
+        .filter_map(|profile| {
+            VideoCapabilities::query_decode(&video_queue, physical_device, profile)
+                .map(|capabilities| (profile, capabilities))
+        })
+        .collect();
+    // Per profile, so an H.265 query doesn't chain its structures on a device that only has the
+    // H.264 encode extension (or the other way around)
+    let encode_capabilities = PROFILES
+        .into_iter()
+        .filter(|&profile| supported(profile, Operation::Encode))
+        .filter_map(|profile| {
+            EncodeCapabilities::query(&video_queue, physical_device, profile)
+                .map(|capabilities| (profile, capabilities))
+        })
+        .collect();
+
+    let workarounds = workarounds::for_device(instance, physical_device);
+
+    Ok(PhysicalDeviceInfo {
         physical_device,
         supported_codecs,
         decode_queue_family,
+        encode_queue_family,
+        compute_queue_family,
+        memory_budget_supported,
+        decode_capabilities,
+        encode_capabilities,
+        workarounds,
     })
 }
 
-impl Drop for VulkanData {
-    fn drop(&mut self) {
-        unsafe {
-            self.debug_utils_loader
-                .destroy_debug_utils_messenger(self.debug_call_back, None);
-            self.instance.destroy_instance(None);
+/// Number of attempts at setting up the instance and physical device.
+const INIT_ATTEMPTS: u32 = 3;
+const INIT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Runs `f`, retrying with an increasing delay if it fails with VK_ERROR_INITIALIZATION_FAILED.
+///
+/// In cloud vGPU guests, instance creation and device enumeration sometimes fail transiently
+/// while the host side of the GPU is busy (e.g. right after the VM was started or migrated).
+/// Devices without (usable) video support fail with VK_ERROR_FEATURE_NOT_PRESENT instead, which
+/// won't change on a retry.
+fn retry_initialization<T>(what: &str, mut f: impl FnMut() -> VkResult<T>) -> VkResult<T> {
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Err(vk::Result::ERROR_INITIALIZATION_FAILED) if attempt < INIT_ATTEMPTS => {
+                warn!("Failed to {what} (attempt {attempt} of {INIT_ATTEMPTS}), retrying");
+                std::thread::sleep(INIT_RETRY_DELAY * attempt);
+                attempt += 1;
+            }
+            result => return result,
         }
     }
 }
 
+fn init_vulkan(device_id: DeviceId) -> VkResult<VulkanData> {
+    let (shared, device) = retry_initialization("set up the Vulkan device", || {
+        let shared = SharedInstance::get()?;
+        let device = shared.physical_device(device_id)?;
+        Ok((shared, device))
+    })?;
+
+    let memory_budget = MemoryBudget::new(
+        &shared.instance,
+        device.physical_device,
+        device.memory_budget_supported,
+    );
+
+    Ok(VulkanData {
+        instance: shared.instance.clone(),
+        shared,
+        physical_device: device.physical_device,
+        supported_codecs: device.supported_codecs,
+        decode_queue_family: device.decode_queue_family,
+        encode_queue_family: device.encode_queue_family,
+        compute_queue_family: device.compute_queue_family,
+        decode_capabilities: device.decode_capabilities,
+        encode_capabilities: device.encode_capabilities,
+        workarounds: device.workarounds,
+        memory_budget: Mutex::new(memory_budget),
+    })
+}
+
 const PROFILES: [VAProfile; 39] = [
     va_backend_sys::VAProfile_VAProfileNone,
     va_backend_sys::VAProfile_VAProfileMPEG2Simple,
@@ -1083,57 +2054,59 @@ fn vk_video_profile_info_for_va_profile(va_profile: VAProfile) -> Option<Partial
     }
 }
 
-#[derive(Debug, Copy, Clone)]
-#[repr(i32)]
-#[allow(dead_code)]
-enum VaError {
-    OperationFailed = va_backend_sys::VA_STATUS_ERROR_OPERATION_FAILED as VAStatus,
-    AllocationFailed = va_backend_sys::VA_STATUS_ERROR_ALLOCATION_FAILED as VAStatus,
-    InvalidDisplay = va_backend_sys::VA_STATUS_ERROR_INVALID_DISPLAY as VAStatus,
-    InvalidConfig = va_backend_sys::VA_STATUS_ERROR_INVALID_CONFIG as VAStatus,
-    InvalidContext = va_backend_sys::VA_STATUS_ERROR_INVALID_CONTEXT as VAStatus,
-    InvalidSurface = va_backend_sys::VA_STATUS_ERROR_INVALID_SURFACE as VAStatus,
-    InvalidBuffer = va_backend_sys::VA_STATUS_ERROR_INVALID_BUFFER as VAStatus,
-    InvalidImage = va_backend_sys::VA_STATUS_ERROR_INVALID_IMAGE as VAStatus,
-    InvalidSubpicture = va_backend_sys::VA_STATUS_ERROR_INVALID_SUBPICTURE as VAStatus,
-    AttrNotSupported = va_backend_sys::VA_STATUS_ERROR_ATTR_NOT_SUPPORTED as VAStatus,
-    MaxNumExceeded = va_backend_sys::VA_STATUS_ERROR_MAX_NUM_EXCEEDED as VAStatus,
-    UnsupportedProfile = va_backend_sys::VA_STATUS_ERROR_UNSUPPORTED_PROFILE as VAStatus,
-    UnsupportedEntrypoint = va_backend_sys::VA_STATUS_ERROR_UNSUPPORTED_ENTRYPOINT as VAStatus,
-    UnsupportedRtformat = va_backend_sys::VA_STATUS_ERROR_UNSUPPORTED_RT_FORMAT as VAStatus,
-    UnsupportedBuffertype = va_backend_sys::VA_STATUS_ERROR_UNSUPPORTED_BUFFERTYPE as VAStatus,
-    SurfaceBusy = va_backend_sys::VA_STATUS_ERROR_SURFACE_BUSY as VAStatus,
-    FlagNotSupported = va_backend_sys::VA_STATUS_ERROR_FLAG_NOT_SUPPORTED as VAStatus,
-    InvalidParameter = va_backend_sys::VA_STATUS_ERROR_INVALID_PARAMETER as VAStatus,
-    ResolutionNotSupported = va_backend_sys::VA_STATUS_ERROR_RESOLUTION_NOT_SUPPORTED as VAStatus,
-    Unimplemented = va_backend_sys::VA_STATUS_ERROR_UNIMPLEMENTED as VAStatus,
-    SurfaceInDisplaying = va_backend_sys::VA_STATUS_ERROR_SURFACE_IN_DISPLAYING as VAStatus,
-    InvalidImageFormat = va_backend_sys::VA_STATUS_ERROR_INVALID_IMAGE_FORMAT as VAStatus,
-    DecodingError = va_backend_sys::VA_STATUS_ERROR_DECODING_ERROR as VAStatus,
-    EncodingError = va_backend_sys::VA_STATUS_ERROR_ENCODING_ERROR as VAStatus,
-}
-
-impl From<VaError> for VAStatus {
-    fn from(err: VaError) -> Self {
-        err as VAStatus
-    }
-}
-
-impl fmt::Display for VaError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
-    }
-}
-
-impl std::error::Error for VaError {}
-
 /// A device identifier consisting of major and minor numbers.
 /// While `major`/`minor` return `u32`, we use `i64` to match the types used by vulkan's
 /// `VkPhysicalDeviceDrmPropertiesEXT`, since u32 can trivially be converted to i64 but not vice
 /// versa.
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 struct DeviceId(i64, i64);
 
+/// PCI address (domain:bus:device.function) of a device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct PciAddress {
+    domain: u32,
+    bus: u32,
+    device: u32,
+    function: u32,
+}
+
+impl PciAddress {
+    /// Looks up the PCI address of the DRM device `device_id` in sysfs, where
+    /// `/sys/dev/char/<major>:<minor>/device` links to the PCI device, e.g. `.../0000:03:00.0`.
+    fn of_drm_device(device_id: DeviceId) -> Option<Self> {
+        let path = format!("/sys/dev/char/{}:{}/device", device_id.0, device_id.1);
+        let target = match std::fs::read_link(&path) {
+            Ok(target) => target,
+            Err(err) => {
+                debug!("Can't resolve {path}: {err}");
+                return None;
+            }
+        };
+        let name = target.file_name()?.to_str()?;
+
+        let (domain, rest) = name.split_once(':')?;
+        let (bus, rest) = rest.split_once(':')?;
+        let (device, function) = rest.split_once('.')?;
+        let parse = |value: &str| u32::from_str_radix(value, 16).ok();
+        Some(Self {
+            domain: parse(domain)?,
+            bus: parse(bus)?,
+            device: parse(device)?,
+            function: parse(function)?,
+        })
+    }
+}
+
+impl fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04x}:{:02x}:{:02x}.{:x}",
+            self.domain, self.bus, self.device, self.function
+        )
+    }
+}
+
 unsafe fn extract_drm_device_id(driver_context: &mut VADriverContext) -> Result<DeviceId, VaError> {
     // > This structure is allocated from libva with calloc().
     // > All structures shall be derived from struct drm_state.
@@ -1150,22 +2123,14 @@ unsafe fn extract_drm_device_id(driver_context: &mut VADriverContext) -> Result<
             .expect("driver_context.drm_state is null after is_null() was checked")
     };
 
-    let drm_fd = RawFd::from(drm_state.fd);
-
-    if drm_fd < 0 {
-        error!("Invalid DRM file descriptor: {}", drm_fd);
-        return Err(VaError::InvalidParameter);
-    }
-
     info!(
-        "DRM state: FD = {drm_fd:?}, Auth type = {:?}",
-        drm_state.auth_type
+        "DRM state: FD = {}, Auth type = {:?}",
+        drm_state.fd, drm_state.auth_type
     );
 
-    // Temporarily take ownership of the DRM fd to extract its metadata.
-    let drm_file = unsafe { File::from_raw_fd(drm_fd) };
-    let metadata = drm_file.metadata();
-    let _drm_fd = drm_file.into_raw_fd(); // Prevent the file from being closed when drm_file goes out of scope
+    // SAFETY: libva keeps the DRM fd open while the display exists.
+    let drm_fd = unsafe { fd::borrow(drm_state.fd)? };
+    let metadata = fd::metadata(drm_fd);
 
     // Extract st_rdev from metadata to identify the device
     // See the implementation of libdrm's drmGetDevice2 for more details
@@ -1173,13 +2138,16 @@ unsafe fn extract_drm_device_id(driver_context: &mut VADriverContext) -> Result<
     let metadata = match metadata {
         Ok(metadata) => metadata,
         Err(err) => {
-            error!("Failed to get metadata for DRM fd {}: {:?}", drm_fd, err);
+            error!(
+                "Failed to get metadata for DRM fd {}: {:?}",
+                drm_state.fd, err
+            );
             return Err(VaError::OperationFailed);
         }
     };
 
     if !metadata.file_type().is_char_device() {
-        error!("DRM fd {} is not a character device", drm_fd);
+        error!("DRM fd {} is not a character device", drm_state.fd);
         return Err(VaError::InvalidParameter);
     }
 
@@ -1193,14 +2161,294 @@ unsafe fn extract_drm_device_id(driver_context: &mut VADriverContext) -> Result<
     Ok(DeviceId(major.into(), minor.into()))
 }
 
+/// Driver-specific display attribute types start here. libva only defines the standard
+/// `VADisplayAttribType` values, so ours are kept well clear of them.
+const DISPLAY_ATTRIB_DRIVER_BASE: VADisplayAttribType = 0x1000;
+
+/// Display attributes exposed through vaQueryDisplayAttributes and friends.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum DisplayAttrib {
+    /// Make a decoded surface available as soon as its own submission has completed (value 1).
+    ///
+    /// The value is stored, but has no effect yet: pictures aren't submitted at all so far. Once
+    /// they are, output may be held back by default so submissions can be batched, and this
+    /// disables the batching (see [`DisplaySettings::max_batched_pictures`]). That is only
+    /// correct for streams without reordering, e.g. WebRTC.
+    LowLatency,
+    /// Scheduling priority of the display's queues: 0 = low, 1 = normal, 2 = high. See
+    /// [`ContextPriority`].
+    Priority,
+    /// Trade-off between power usage and latency: 0 = balanced, 1 = power saving, 2 = latency.
+    /// See [`PowerProfile`].
+    PowerProfile,
+    /// Read-only: crate version as `major << 16 | minor << 8 | patch`. The commit is only in the
+    /// vendor string.
+    Version,
+    /// Read-only: bit mask of the codec features the driver was built with: 1 = H.264,
+    /// 2 = H.265, 4 = AV1, 8 = VP9, 16 = encode, 32 = video processing. Whether the device
+    /// supports them is still up to vaQueryConfigProfiles.
+    Features,
+    /// Read-only: deviation of the actual bitrate over the last second from the target bitrate of
+    /// the most recently active encode context, in percent. Positive values are overshoot. 0 if
+    /// no encode context has produced pictures yet.
+    RateDeviation,
+    /// Read-only: HRD buffer fullness of the most recently active encode context, in percent.
+    /// Above 100 the buffer overflowed. 0 if no encode context has produced pictures yet.
+    HrdFullness,
+}
+
+impl DisplayAttrib {
+    /// All attributes, in declaration order.
+    const ALL: [Self; 7] = [
+        Self::LowLatency,
+        Self::Priority,
+        Self::PowerProfile,
+        Self::Version,
+        Self::Features,
+        Self::RateDeviation,
+        Self::HrdFullness,
+    ];
+
+    fn va_type(self) -> VADisplayAttribType {
+        DISPLAY_ATTRIB_DRIVER_BASE + self as VADisplayAttribType
+    }
+
+    fn from_va(va_type: VADisplayAttribType) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|attrib| attrib.va_type() == va_type)
+    }
+
+    fn settable(self) -> bool {
+        !matches!(
+            self,
+            Self::Version | Self::Features | Self::RateDeviation | Self::HrdFullness
+        )
+    }
+
+    /// Inclusive range of accepted values. Read-only attributes only have their current value.
+    fn range(self) -> (i32, i32) {
+        match self {
+            Self::LowLatency => (0, 1),
+            Self::Priority => (0, ContextPriority::High as i32),
+            Self::PowerProfile => (0, PowerProfile::Latency as i32),
+            Self::Version => (build_info::VERSION, build_info::VERSION),
+            Self::Features => (build_info::FEATURES, build_info::FEATURES),
+            Self::RateDeviation => (-100, i32::MAX),
+            Self::HrdFullness => (0, i32::MAX),
+        }
+    }
+
+    fn get(self, settings: &DisplaySettings, rate_stats: Option<&RateStats>) -> i32 {
+        match self {
+            Self::LowLatency => settings.low_latency as i32,
+            Self::Priority => settings.priority as i32,
+            Self::PowerProfile => settings.power_profile as i32,
+            Self::Version => build_info::VERSION,
+            Self::Features => build_info::FEATURES,
+            Self::RateDeviation => rate_stats.map_or(0, |stats| {
+                stats.deviation_percent().clamp(-100, i32::MAX.into()) as i32
+            }),
+            Self::HrdFullness => rate_stats.map_or(0, |stats| {
+                stats.buffer_fullness_percent().min(i32::MAX as u64) as i32
+            }),
+        }
+    }
+
+    fn set(self, settings: &mut DisplaySettings, value: i32) {
+        match self {
+            Self::LowLatency => settings.low_latency = value != 0,
+            Self::Priority => {
+                settings.priority = match value {
+                    0 => ContextPriority::Low,
+                    1 => ContextPriority::Normal,
+                    _ => ContextPriority::High,
+                }
+            }
+            Self::PowerProfile => {
+                settings.power_profile = match value {
+                    0 => PowerProfile::Balanced,
+                    1 => PowerProfile::PowerSaving,
+                    _ => PowerProfile::Latency,
+                }
+            }
+            Self::Version | Self::Features | Self::RateDeviation | Self::HrdFullness => {
+                unreachable!("read-only attributes are rejected by vaSetDisplayAttributes")
+            }
+        }
+    }
+
+    fn describe(
+        self,
+        settings: &DisplaySettings,
+        rate_stats: Option<&RateStats>,
+    ) -> VADisplayAttribute {
+        let (min_value, max_value) = self.range();
+        VADisplayAttribute {
+            type_: self.va_type(),
+            min_value,
+            max_value,
+            value: self.get(settings, rate_stats),
+            flags: if self.settable() {
+                va_backend_sys::VA_DISPLAY_ATTRIB_GETTABLE
+                    | va_backend_sys::VA_DISPLAY_ATTRIB_SETTABLE
+            } else {
+                va_backend_sys::VA_DISPLAY_ATTRIB_GETTABLE
+            },
+            va_reserved: Default::default(),
+        }
+    }
+}
+
+/// Scheduling priority of the queues of a display, mapped to VK_KHR_global_priority.
+///
+/// Queues are created with the priority that is set when the first context is created, changing
+/// it afterwards doesn't affect existing queues.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+enum ContextPriority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// Environment variable overriding the default [`ContextPriority`] of all displays in a process,
+/// one of `low`, `normal` or `high`.
+const PRIORITY_ENV: &str = "VAVK_PRIORITY";
+
+/// How submissions and synchronization trade power usage against latency.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+enum PowerProfile {
+    #[default]
+    Balanced,
+    /// Batch submissions to keep the GPU idle for longer, and block in vaSyncSurface. Meant for
+    /// laptops on battery.
+    PowerSaving,
+    /// Submit immediately, and busy-wait on fences in vaSyncSurface instead of sleeping. Meant
+    /// for streaming, at the cost of a CPU core while waiting.
+    Latency,
+}
+
+/// Environment variable overriding the default [`PowerProfile`] of all displays in a process,
+/// one of `balanced`, `power-saving` or `latency`.
+const POWER_PROFILE_ENV: &str = "VAVK_POWER_PROFILE";
+
+/// What to do when a client writes to a surface that is still the target of in-flight GPU work.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+enum BusySurfaces {
+    /// Fail with VA_STATUS_ERROR_SURFACE_BUSY, as the VA API intends.
+    #[default]
+    Error,
+    /// Wait for the work to finish. For simpler clients that don't handle SURFACE_BUSY.
+    Wait,
+}
+
+/// Environment variable overriding [`BusySurfaces`], one of `error` or `wait`.
+const BUSY_SURFACES_ENV: &str = "VAVK_BUSY_SURFACES";
+
+/// [`DisplayAttrib::ALL`] must list every attribute exactly once, so that vaQueryDisplayAttributes
+/// (bounded by vaMaxNumDisplayAttributes) reports each of them.
+const _: () = {
+    let mut i = 0;
+    while i < DisplayAttrib::ALL.len() {
+        assert!(DisplayAttrib::ALL[i] as usize == i);
+        i += 1;
+    }
+};
+
+/// Per-display settings that clients can change through display attributes.
+#[derive(Debug, Default)]
+struct DisplaySettings {
+    /// See [`DisplayAttrib::LowLatency`].
+    low_latency: bool,
+    /// See [`DisplayAttrib::Priority`].
+    priority: ContextPriority,
+    /// See [`DisplayAttrib::PowerProfile`].
+    power_profile: PowerProfile,
+    /// Only set from the environment.
+    busy_surfaces: BusySurfaces,
+}
+
+impl DisplaySettings {
+    /// Default settings, with overrides from the environment applied.
+    fn from_env() -> Self {
+        let mut settings = Self::default();
+
+        if let Ok(value) = std::env::var(PRIORITY_ENV) {
+            match value.trim() {
+                "low" => settings.priority = ContextPriority::Low,
+                "normal" => settings.priority = ContextPriority::Normal,
+                "high" => settings.priority = ContextPriority::High,
+                _ => warn!("Ignoring invalid {PRIORITY_ENV}={value:?}"),
+            }
+        }
+
+        if let Ok(value) = std::env::var(POWER_PROFILE_ENV) {
+            match value.trim() {
+                "balanced" => settings.power_profile = PowerProfile::Balanced,
+                "power-saving" => settings.power_profile = PowerProfile::PowerSaving,
+                "latency" => settings.power_profile = PowerProfile::Latency,
+                _ => warn!("Ignoring invalid {POWER_PROFILE_ENV}={value:?}"),
+            }
+        }
+
+        if let Ok(value) = std::env::var(BUSY_SURFACES_ENV) {
+            match value.trim() {
+                "error" => settings.busy_surfaces = BusySurfaces::Error,
+                "wait" => settings.busy_surfaces = BusySurfaces::Wait,
+                _ => warn!("Ignoring invalid {BUSY_SURFACES_ENV}={value:?}"),
+            }
+        }
+
+        settings
+    }
+
+    /// Number of pictures that may be held back and submitted together. 1 means every picture is
+    /// submitted immediately.
+    #[allow(dead_code)] // Used once pictures are submitted
+    fn max_batched_pictures(&self) -> usize {
+        if self.low_latency {
+            return 1;
+        }
+        match self.power_profile {
+            PowerProfile::Balanced => 2,
+            PowerProfile::PowerSaving => 4,
+            PowerProfile::Latency => 1,
+        }
+    }
+
+    /// Whether to spin on fences instead of blocking when synchronizing with the GPU.
+    #[allow(dead_code)] // Used once surfaces can be synchronized
+    fn busy_wait_sync(&self) -> bool {
+        self.power_profile == PowerProfile::Latency
+    }
+
+    /// Result of writing to a surface that is still the target of in-flight GPU work: either
+    /// [`VaError::SurfaceBusy`], or `Ok` if the caller should wait for the work instead.
+    #[allow(dead_code)] // Used once surfaces track their in-flight work
+    fn surface_busy(&self) -> Result<(), VaError> {
+        match self.busy_surfaces {
+            BusySurfaces::Error => Err(VaError::SurfaceBusy),
+            BusySurfaces::Wait => Ok(()),
+        }
+    }
+}
+
 struct DriverData {
     magic: u32,
     vulkan: VulkanData,
+    settings: DisplaySettings,
+    quirks: Quirks,
+    configs: ObjectTable<Config>,
+    /// Statistics of the most recently active encode context, see [`DisplayAttrib::RateDeviation`].
+    rate_stats: Option<RateStats>,
 }
 
 impl DriverData {
     const MAGIC: u32 = 0x5641564b; // "VAVK"
 
+    const CONFIG_ID_BASE: u32 = 0x0100_0000;
+
     unsafe fn from_ptr<'a>(ptr: *mut c_void) -> Result<&'a mut Self, VaError> {
         let ptr: *mut Self = ptr.cast();
         if ptr.is_null() || !ptr.is_aligned() {
@@ -1268,29 +2516,39 @@ unsafe fn va_driver_init(driver_context: VADriverContextP) -> Result<(), VaError
 
     println!("{driver_context:#?}");
 
-    // TODO: actual max values
     driver_context.max_profiles = PROFILES.len() as c_int;
     driver_context.max_entrypoints = MAX_ENTRYPOINTS as c_int; // VAEntrypointVLD, VAEntrypointEncSlice
-    driver_context.max_attributes = 1;
-    driver_context.max_image_formats = 1;
-    driver_context.max_subpic_formats = 1;
+    driver_context.max_attributes = config::CONFIG_ATTRIBS.len() as c_int;
+    driver_context.max_image_formats = image_formats::IMAGE_FORMATS.len() as c_int;
+    driver_context.max_subpic_formats = image_formats::SUBPICTURE_FORMATS.len() as c_int;
+    driver_context.max_display_attributes = DisplayAttrib::ALL.len() as c_int;
 
-    driver_context.str_vendor = VENDOR.as_ptr();
+    driver_context.str_vendor = build_info::VENDOR.as_ptr();
 
     fill_vtable(vtable);
 
     // Initialize Vulkan and select a physical device matching the DRM device.
     let drm_device_id = unsafe { extract_drm_device_id(driver_context)? };
+    info!(
+        "Initializing display on DRM device {}/{}",
+        drm_device_id.0, drm_device_id.1
+    );
 
-    let vulkan_data = init_vulkan(drm_device_id).map_err(|err| {
-        error!("Failed to initialize Vulkan: {:?}", err);
-        VaError::OperationFailed
-    })?;
+    let vulkan_data = init_vulkan(drm_device_id)
+        .map_err(|err| VaError::from(err).context("failed to initialize Vulkan"))?;
+
+    if self_test::enabled() {
+        self_test::run(&vulkan_data)?;
+    }
 
     // Attach our driver data to the context so we can access it in the other functions.
     let driver_data = Box::new(DriverData {
         magic: DriverData::MAGIC,
         vulkan: vulkan_data,
+        settings: DisplaySettings::from_env(),
+        quirks: Quirks::load(),
+        configs: ObjectTable::new(DriverData::CONFIG_ID_BASE),
+        rate_stats: None,
     });
     driver_context.pDriverData = Box::into_raw(driver_data).cast();
 
@@ -1303,17 +2561,23 @@ unsafe fn va_driver_init(driver_context: VADriverContextP) -> Result<(), VaError
 /// doesn't (yet) validate the contents of the structure.
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn __vaDriverInit_1_22(driver_context: VADriverContextP) -> VAStatus {
-    // Initialize the logger. Should only return an error if it's already been initialized.
-    let _ = SimpleLogger::new().init();
-    log::set_max_level(log::LevelFilter::Trace);
+    // Processes may open several displays (e.g. one per GPU), but the logger is global.
+    static INIT_LOGGER: Once = Once::new();
+    INIT_LOGGER.call_once(|| {
+        // Should only return an error if the host already installed a logger.
+        let _ = SimpleLogger::new().init();
+        log::set_max_level(log::LevelFilter::Trace);
+    });
 
     debug!("__vaDriverInit_1_22 called");
+    info!("{}", build_info::VENDOR.to_string_lossy());
 
-    let result = unsafe { va_driver_init(driver_context) };
+    let result = catch_panic(|| unsafe { va_driver_init(driver_context) });
     match result {
         Ok(()) => VA_STATUS_SUCCESS as VAStatus,
         Err(err) => {
-            // Don't log here, the invoker usually does that
+            // The invoker usually logs the status, but it doesn't know about the context
+            err.log_returned(Location::caller());
             err.into()
         }
     }
@@ -1321,3 +2585,46 @@ pub unsafe extern "C" fn __vaDriverInit_1_22(driver_context: VADriverContextP) -
 
 /// Compile-time check to ensure the vaDriverInit function conforms to the expected type.
 const _DRIVER_INIT: VADriverInit = Some(__vaDriverInit_1_22);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(result: Result<(), VaError>) -> VAStatus {
+        result.map_or_else(VAStatus::from, |()| VA_STATUS_SUCCESS as VAStatus)
+    }
+
+    #[test]
+    fn write_list_exact_capacity() {
+        let mut list = [0u32; 3];
+        let result = unsafe { write_list(list.as_mut_ptr(), 3, &[1, 2, 3]) };
+        assert_eq!(status(result), VA_STATUS_SUCCESS as VAStatus);
+        assert_eq!(list, [1, 2, 3]);
+    }
+
+    #[test]
+    fn write_list_one_short() {
+        let mut list = [0u32; 3];
+        let result = unsafe { write_list(list.as_mut_ptr(), 2, &[1, 2, 3]) };
+        assert_eq!(status(result), VaError::MaxNumExceeded.into());
+        assert_eq!(list, [0, 0, 0]);
+    }
+
+    #[test]
+    fn write_list_negative_capacity() {
+        let mut list = [0u32; 1];
+        let result = unsafe { write_list(list.as_mut_ptr(), -1, &[1]) };
+        assert_eq!(status(result), VaError::MaxNumExceeded.into());
+        assert_eq!(list, [0]);
+    }
+
+    #[test]
+    fn write_list_empty() {
+        let mut list = [7u32; 1];
+        for capacity in [-1, 0, 1] {
+            let result = unsafe { write_list(list.as_mut_ptr(), capacity, &[]) };
+            assert_eq!(status(result), VA_STATUS_SUCCESS as VAStatus);
+        }
+        assert_eq!(list, [7]);
+    }
+}
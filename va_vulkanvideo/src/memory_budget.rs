@@ -0,0 +1,168 @@
+//! Accounting of device memory allocations against the heap budgets reported by
+//! VK_EXT_memory_budget.
+//!
+//! Running out of device memory usually surfaces much later as VK_ERROR_DEVICE_LOST or an
+//! allocation failure deep inside a submission. Checking surface and session allocations against
+//! the budget up front lets us return VA_STATUS_ERROR_ALLOCATION_FAILED to the client instead.
+
+use ash::vk;
+use log::{debug, error, warn};
+
+use crate::VaError;
+
+/// Environment variable holding an optional soft limit (in MiB) on the device memory a single
+/// display may allocate. Useful on transcoding servers where several processes share a GPU.
+const MEMORY_LIMIT_ENV: &str = "VAVK_MEMORY_LIMIT_MB";
+
+#[derive(Debug, Copy, Clone)]
+struct HeapBudget {
+    /// Bytes the process can allocate from this heap, as estimated by the implementation.
+    budget: vk::DeviceSize,
+    /// Bytes the process currently uses from this heap.
+    usage: vk::DeviceSize,
+}
+
+pub(crate) struct MemoryBudget {
+    /// Whether VK_EXT_memory_budget is supported. Without it, the heap sizes serve as the budget
+    /// and our own allocations are the only known usage.
+    ext_supported: bool,
+    /// Bytes allocated through [`MemoryBudget::reserve`], per heap.
+    allocated: [vk::DeviceSize; vk::MAX_MEMORY_HEAPS],
+    /// Soft limit on the sum of `allocated`, from [`MEMORY_LIMIT_ENV`].
+    soft_limit: Option<vk::DeviceSize>,
+}
+
+impl MemoryBudget {
+    pub(crate) fn new(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        ext_supported: bool,
+    ) -> Self {
+        let soft_limit = std::env::var(MEMORY_LIMIT_ENV).ok().and_then(|value| {
+            match value.trim().parse::<vk::DeviceSize>() {
+                Ok(mib) => {
+                    let limit = mib.checked_mul(1024 * 1024);
+                    if limit.is_none() {
+                        warn!("Ignoring {MEMORY_LIMIT_ENV}={value:?}, it's too large");
+                    }
+                    limit
+                }
+                Err(err) => {
+                    warn!("Ignoring invalid {MEMORY_LIMIT_ENV}={value:?}: {err}");
+                    None
+                }
+            }
+        });
+
+        let budget = Self {
+            ext_supported,
+            allocated: [0; vk::MAX_MEMORY_HEAPS],
+            soft_limit,
+        };
+
+        if !ext_supported {
+            warn!("VK_EXT_memory_budget is not supported, only heap sizes are used as budget");
+        }
+        for (i, heap) in budget.query(instance, physical_device).iter().enumerate() {
+            debug!(
+                "Memory heap {i}: budget = {} MiB, usage = {} MiB",
+                heap.budget / (1024 * 1024),
+                heap.usage / (1024 * 1024)
+            );
+        }
+        if let Some(soft_limit) = soft_limit {
+            debug!("Memory soft limit: {} MiB", soft_limit / (1024 * 1024));
+        }
+
+        budget
+    }
+
+    fn query(
+        &self,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+    ) -> Vec<HeapBudget> {
+        let mut budget_properties = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        let mut properties2 = vk::PhysicalDeviceMemoryProperties2::default();
+        if self.ext_supported {
+            properties2 = properties2.push_next(&mut budget_properties);
+        }
+        unsafe {
+            instance.get_physical_device_memory_properties2(physical_device, &mut properties2)
+        };
+
+        let heap_sizes = properties2
+            .memory_properties
+            .memory_heaps_as_slice()
+            .iter()
+            .map(|heap| heap.size)
+            .collect::<Vec<_>>();
+
+        heap_sizes
+            .into_iter()
+            .enumerate()
+            .map(|(i, size)| {
+                if self.ext_supported {
+                    HeapBudget {
+                        budget: budget_properties.heap_budget[i],
+                        usage: budget_properties.heap_usage[i],
+                    }
+                } else {
+                    HeapBudget {
+                        budget: size,
+                        usage: self.allocated[i],
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Accounts for an allocation of `size` bytes from `heap_index`, failing with
+    /// [`VaError::AllocationFailed`] if it would exceed the heap budget or the soft limit.
+    ///
+    /// Every successful reservation must be paired with a [`MemoryBudget::release`] once the
+    /// memory is freed.
+    pub(crate) fn reserve(
+        &mut self,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        heap_index: u32,
+        size: vk::DeviceSize,
+    ) -> Result<(), VaError> {
+        let heap_index = heap_index as usize;
+        let heaps = self.query(instance, physical_device);
+        let Some(heap) = heaps.get(heap_index) else {
+            error!("Invalid memory heap index {heap_index}");
+            return Err(VaError::InvalidParameter);
+        };
+
+        if heap.usage.saturating_add(size) > heap.budget {
+            error!(
+                "Allocating {size} bytes from heap {heap_index} would exceed its budget \
+                (usage = {}, budget = {})",
+                heap.usage, heap.budget
+            );
+            return Err(VaError::AllocationFailed);
+        }
+
+        if let Some(soft_limit) = self.soft_limit {
+            let total = self.allocated.iter().sum::<vk::DeviceSize>();
+            if total.saturating_add(size) > soft_limit {
+                error!(
+                    "Allocating {size} bytes would exceed the soft limit set by \
+                    {MEMORY_LIMIT_ENV} (allocated = {total}, limit = {soft_limit})"
+                );
+                return Err(VaError::AllocationFailed);
+            }
+        }
+
+        self.allocated[heap_index] += size;
+        Ok(())
+    }
+
+    /// Returns memory previously accounted for with [`MemoryBudget::reserve`].
+    pub(crate) fn release(&mut self, heap_index: u32, size: vk::DeviceSize) {
+        let allocated = &mut self.allocated[heap_index as usize];
+        *allocated = allocated.saturating_sub(size);
+    }
+}
@@ -0,0 +1,55 @@
+//! Tables mapping the IDs handed out to VA clients to driver objects.
+
+use std::collections::{HashMap, hash_map::Entry};
+
+/// Objects of one kind, e.g. configs.
+///
+/// Each kind of object gets its own ID range, so that passing e.g. a surface ID where a context
+/// ID is expected is detected instead of silently referring to an unrelated object.
+pub(crate) struct ObjectTable<T> {
+    objects: HashMap<u32, T>,
+    base: u32,
+    next: u32,
+}
+
+impl<T> ObjectTable<T> {
+    /// Size of the ID range of each kind of object.
+    const RANGE: u32 = 0x0100_0000;
+
+    /// Creates an empty table handing out IDs from `base` to `base + 0xffffff`. `base` must be a
+    /// multiple of 0x1000000 and not 0xff000000, so no ID is `VA_INVALID_ID`.
+    pub(crate) fn new(base: u32) -> Self {
+        assert!(base.is_multiple_of(Self::RANGE) && base != 0xff00_0000);
+        Self {
+            objects: HashMap::new(),
+            base,
+            next: 0,
+        }
+    }
+
+    /// Stores `object`, returning its new ID, or `None` if all IDs are in use.
+    pub(crate) fn insert(&mut self, object: T) -> Option<u32> {
+        if self.objects.len() >= Self::RANGE as usize {
+            return None;
+        }
+
+        // IDs are reused only after wrapping around, which makes use-after-destroy bugs in
+        // clients show up as invalid IDs.
+        loop {
+            let id = self.base + self.next;
+            self.next = (self.next + 1) % Self::RANGE;
+            if let Entry::Vacant(entry) = self.objects.entry(id) {
+                entry.insert(object);
+                return Some(id);
+            }
+        }
+    }
+
+    pub(crate) fn get(&self, id: u32) -> Option<&T> {
+        self.objects.get(&id)
+    }
+
+    pub(crate) fn remove(&mut self, id: u32) -> Option<T> {
+        self.objects.remove(&id)
+    }
+}
@@ -0,0 +1,65 @@
+//! Deduplication of the parameter sets (SPS/PPS/VPS, AV1 sequence headers) of a context.
+//!
+//! VA has no separate parameter set buffers, they are embedded in the picture parameters of every
+//! frame, and some muxers repeat the bitstream parameter sets every frame as well. Only parameter
+//! sets that actually changed need to be added to the VkVideoSessionParametersKHR, so the last
+//! contents of each one are remembered by hash.
+
+// Not wired up until session parameters are created.
+#![allow(dead_code)]
+
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum ParameterSetKind {
+    /// H.265 video parameter set.
+    Vps,
+    /// H.264/H.265 sequence parameter set, AV1 sequence header.
+    Sps,
+    /// H.264/H.265 picture parameter set.
+    Pps,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct ParameterSetCache {
+    /// Hash of the contents currently in the session parameters, by kind and ID.
+    hashes: HashMap<(ParameterSetKind, u32), u64>,
+    /// `updateSequenceCount` of the last vkUpdateVideoSessionParametersKHR call.
+    update_sequence_count: u32,
+}
+
+impl ParameterSetCache {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records `contents` as parameter set `kind`/`id`, returning whether it differs from what is
+    /// in the session parameters, i.e. whether it has to be added with an update.
+    pub(crate) fn changed(
+        &mut self,
+        kind: ParameterSetKind,
+        id: u32,
+        contents: &impl Hash,
+    ) -> bool {
+        let mut hasher = DefaultHasher::new();
+        contents.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        self.hashes.insert((kind, id), hash) != Some(hash)
+    }
+
+    /// `updateSequenceCount` for the next vkUpdateVideoSessionParametersKHR call. The spec requires
+    /// it to be one more than the previous one.
+    pub(crate) fn next_update_sequence_count(&mut self) -> u32 {
+        self.update_sequence_count += 1;
+        self.update_sequence_count
+    }
+
+    /// Forgets all parameter sets, after the session parameters object was recreated.
+    pub(crate) fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
@@ -0,0 +1,158 @@
+//! Pipeline cache for the VPP and CSC compute shaders, persisted on disk.
+//!
+//! Browsers start a fresh process (and display) for many tabs, so without a persistent cache the
+//! shaders are compiled again every time a video starts playing, which shows as a hitch on the
+//! first frames. The cache is stored under `$XDG_CACHE_HOME/vavk`, one file per device
+//! and driver build, and can be disabled with `VAVK_PIPELINE_CACHE=0`.
+
+// Not wired up until compute pipelines are created.
+#![allow(dead_code)]
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use ash::{prelude::*, vk};
+use log::{debug, warn};
+
+/// Environment variable disabling the on-disk cache when set to 0.
+const PIPELINE_CACHE_ENV: &str = "VAVK_PIPELINE_CACHE";
+
+/// Length of `VkPipelineCacheHeaderVersionOne`.
+const HEADER_LEN: usize = 32;
+
+/// Directory the cache files are stored in, or `None` if disabled or no cache directory is set.
+fn cache_dir() -> Option<PathBuf> {
+    if std::env::var_os(PIPELINE_CACHE_ENV).is_some_and(|value| value == "0") {
+        return None;
+    }
+    // Relative paths are invalid according to the XDG base directory spec and have to be ignored
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| {
+            std::env::var_os("HOME")
+                .map(PathBuf::from)
+                .filter(|path| path.is_absolute())
+                .map(|home| home.join(".cache"))
+        })?;
+    Some(base.join("vavk"))
+}
+
+/// File name of the cache for a device. The pipeline cache UUID changes with the driver build,
+/// so updated drivers don't load stale data, and old files are simply left behind.
+fn file_name(properties: &vk::PhysicalDeviceProperties) -> String {
+    let uuid: String = properties
+        .pipeline_cache_uuid
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    format!(
+        "{:04x}-{:04x}-{uuid}.bin",
+        properties.vendor_id, properties.device_id
+    )
+}
+
+/// Whether `data` starts with a `VkPipelineCacheHeaderVersionOne` for the device.
+///
+/// Implementations are required to reject foreign data themselves, but a truncated or otherwise
+/// corrupted file isn't worth finding out how well they do.
+fn header_matches(data: &[u8], properties: &vk::PhysicalDeviceProperties) -> bool {
+    let Some(header) = data.get(..HEADER_LEN) else {
+        return false;
+    };
+    // The header is little-endian regardless of the host byte order
+    let word =
+        |index: usize| u32::from_le_bytes(header[index * 4..index * 4 + 4].try_into().unwrap());
+    word(0) as usize >= HEADER_LEN
+        && word(1) == vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32
+        && word(2) == properties.vendor_id
+        && word(3) == properties.device_id
+        && header[16..32] == properties.pipeline_cache_uuid
+}
+
+pub(crate) struct PipelineCache {
+    pub(crate) cache: vk::PipelineCache,
+    /// File the cache is stored to, `None` if it isn't persisted.
+    path: Option<PathBuf>,
+    /// Size of the data the cache was created with, to skip rewriting an unchanged cache.
+    loaded_len: usize,
+}
+
+impl PipelineCache {
+    /// Creates the pipeline cache, with the data stored by a previous process if there is any.
+    pub(crate) fn load(
+        device: &ash::Device,
+        properties: &vk::PhysicalDeviceProperties,
+    ) -> VkResult<Self> {
+        let path = cache_dir().map(|dir| dir.join(file_name(properties)));
+        let data = match path.as_deref().map(fs::read) {
+            Some(Ok(data)) if header_matches(&data, properties) => data,
+            Some(Ok(_)) => {
+                debug!("Ignoring pipeline cache {path:?} of a different device or driver");
+                Vec::new()
+            }
+            Some(Err(err)) if err.kind() != io::ErrorKind::NotFound => {
+                warn!("Failed to read pipeline cache {path:?}: {err}");
+                Vec::new()
+            }
+            _ => Vec::new(),
+        };
+        debug!(
+            "Loaded {} bytes of pipeline cache from {path:?}",
+            data.len()
+        );
+
+        let create_info = vk::PipelineCacheCreateInfo::default().initial_data(&data);
+        let cache = unsafe { device.create_pipeline_cache(&create_info, None)? };
+        Ok(Self {
+            cache,
+            path,
+            loaded_len: data.len(),
+        })
+    }
+
+    /// Writes the cache back to disk if it grew. Failures are only logged, the cache is just an
+    /// optimization.
+    pub(crate) fn store(&self, device: &ash::Device) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let data = match unsafe { device.get_pipeline_cache_data(self.cache) } {
+            Ok(data) => data,
+            Err(err) => {
+                warn!("Failed to get pipeline cache data: {err}");
+                return;
+            }
+        };
+        if data.len() <= self.loaded_len {
+            return;
+        }
+        if let Err(err) = write_atomically(path, &data) {
+            warn!("Failed to write pipeline cache {path:?}: {err}");
+        } else {
+            debug!("Stored {} bytes of pipeline cache to {path:?}", data.len());
+        }
+    }
+
+    /// # Safety
+    ///
+    /// No pipeline creation using the cache may be in progress.
+    pub(crate) unsafe fn destroy(self, device: &ash::Device) {
+        unsafe { device.destroy_pipeline_cache(self.cache, None) };
+    }
+}
+
+/// Writes `data` to `path` through a temporary file, so concurrently starting processes never
+/// read a partially written cache.
+fn write_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp_path = path.with_extension(format!("tmp{}", std::process::id()));
+    fs::write(&tmp_path, data)?;
+    fs::rename(&tmp_path, path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp_path);
+    })
+}
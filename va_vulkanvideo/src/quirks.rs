@@ -0,0 +1,112 @@
+//! Per-application workarounds ("quirks"), keyed on the process name.
+//!
+//! Some applications make assumptions that hold for the Intel/AMD drivers but not for us. Rather
+//! than changing behavior for everyone, workarounds are enabled for the affected processes only,
+//! similar to Mesa's driconf. The built-in table can be extended in
+//! `$XDG_CONFIG_HOME/vavk/quirks.toml` (`~/.config/vavk/quirks.toml` if unset), with one table per
+//! process name:
+//!
+//! ```toml
+//! [my-player]
+//! nv12_only = true
+//! ```
+//!
+//! An entry in the file replaces the built-in quirks of that process.
+
+use std::{collections::HashMap, path::PathBuf};
+
+use log::{debug, info, warn};
+use serde::Deserialize;
+
+#[derive(Debug, Default, Copy, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Quirks {
+    /// Only report NV12 from vaQueryImageFormats. Chromium assumes NV12 for vaGetImage readbacks
+    /// and gets confused by other formats.
+    pub(crate) nv12_only: bool,
+    /// Allocate coded buffers with headroom, for applications that ignore the overflow bits in
+    /// `VACodedBufferSegment::status` and would otherwise use truncated bitstreams.
+    #[allow(dead_code)] // Used once coded buffers are implemented
+    pub(crate) large_coded_buffers: bool,
+}
+
+/// Built-in quirks, keyed on the process name as in /proc/self/comm.
+const BUILTIN_QUIRKS: [(&str, Quirks); 2] = [
+    (
+        "chrome",
+        Quirks {
+            nv12_only: true,
+            large_coded_buffers: false,
+        },
+    ),
+    (
+        "chromium",
+        Quirks {
+            nv12_only: true,
+            large_coded_buffers: false,
+        },
+    ),
+];
+
+impl Quirks {
+    /// Quirks for the current process.
+    pub(crate) fn load() -> Self {
+        let Some(process_name) = process_name() else {
+            return Self::default();
+        };
+
+        let quirks = user_quirks()
+            .remove(&process_name)
+            .or_else(|| {
+                BUILTIN_QUIRKS
+                    .iter()
+                    .find(|(name, _)| *name == process_name)
+                    .map(|(_, quirks)| *quirks)
+            })
+            .unwrap_or_default();
+        info!("Quirks for {process_name}: {quirks:?}");
+        quirks
+    }
+}
+
+/// Name of the current process, truncated to 15 bytes by the kernel.
+fn process_name() -> Option<String> {
+    match std::fs::read_to_string("/proc/self/comm") {
+        Ok(comm) => Some(comm.trim_end_matches('\n').to_owned()),
+        Err(err) => {
+            warn!("Failed to read the process name, not applying any quirks: {err}");
+            None
+        }
+    }
+}
+
+fn user_quirks_path() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("vavk").join("quirks.toml"))
+}
+
+/// Quirks from the user's quirks file. Errors in the file are logged and the file is ignored.
+fn user_quirks() -> HashMap<String, Quirks> {
+    let Some(path) = user_quirks_path() else {
+        return HashMap::new();
+    };
+
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) => {
+            debug!("Not reading quirks from {}: {err}", path.display());
+            return HashMap::new();
+        }
+    };
+
+    match toml::from_str(&text) {
+        Ok(quirks) => quirks,
+        Err(err) => {
+            warn!("Ignoring invalid quirks file {}: {err}", path.display());
+            HashMap::new()
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! Bitrate statistics of encode contexts.
+//!
+//! Streamers want to know whether the encoder keeps to the target bitrate without parsing the
+//! bitstream. The actual bitrate over a sliding window of one second and the fullness of the
+//! hypothetical reference decoder (HRD) buffer are tracked from the sizes of the coded pictures,
+//! and reported through the read-only display attributes `RateDeviation` and `HrdFullness`.
+
+// Pictures are only recorded once encode contexts exist.
+#![allow(dead_code)]
+
+use std::collections::VecDeque;
+
+pub(crate) struct RateStats {
+    /// Target bitrate in bits per second, from VAEncMiscParameterRateControl.
+    target_bitrate: u64,
+    /// Frame rate as numerator/denominator, from VAEncMiscParameterFrameRate.
+    frame_rate: (u32, u32),
+    /// Sizes in bits of the pictures in the window, newest last.
+    window: VecDeque<u64>,
+    /// Number of pictures in one second, the length of the window.
+    window_len: usize,
+    window_bits: u64,
+    /// HRD buffer size in bits, from VAEncMiscParameterHRD.
+    buffer_size: u64,
+    buffer_fullness: u64,
+}
+
+impl RateStats {
+    pub(crate) fn new(target_bitrate: u64, frame_rate: (u32, u32), buffer_size: u64) -> Self {
+        let (num, den) = (frame_rate.0.max(1), frame_rate.1.max(1));
+        let window_len = num.div_ceil(den) as usize;
+        Self {
+            target_bitrate,
+            frame_rate: (num, den),
+            window: VecDeque::with_capacity(window_len),
+            window_len,
+            window_bits: 0,
+            buffer_size,
+            buffer_fullness: 0,
+        }
+    }
+
+    /// Records a coded picture of `bits` bits.
+    pub(crate) fn record_picture(&mut self, bits: u64) {
+        if self.window.len() == self.window_len
+            && let Some(oldest) = self.window.pop_front()
+        {
+            self.window_bits -= oldest;
+        }
+        self.window.push_back(bits);
+        self.window_bits += bits;
+
+        // The decoder buffer is filled by the picture and drained at the target bitrate
+        let (num, den) = self.frame_rate;
+        let drained = self.target_bitrate * u64::from(den) / u64::from(num);
+        self.buffer_fullness = (self.buffer_fullness + bits).saturating_sub(drained);
+    }
+
+    /// Actual bitrate over the window, in bits per second.
+    pub(crate) fn actual_bitrate(&self) -> u64 {
+        if self.window.is_empty() {
+            return 0;
+        }
+        let (num, den) = self.frame_rate;
+        self.window_bits * u64::from(num) / (u64::from(den) * self.window.len() as u64)
+    }
+
+    /// Deviation of the actual from the target bitrate in percent. Positive values are overshoot,
+    /// negative ones undershoot.
+    pub(crate) fn deviation_percent(&self) -> i64 {
+        if self.target_bitrate == 0 {
+            return 0;
+        }
+        let actual = self.actual_bitrate() as i128;
+        let target = self.target_bitrate as i128;
+        ((actual - target) * 100 / target) as i64
+    }
+
+    /// HRD buffer fullness in percent. Above 100 the buffer overflowed, i.e. a decoder receiving
+    /// the stream at the target bitrate would have stalled.
+    pub(crate) fn buffer_fullness_percent(&self) -> u64 {
+        if self.buffer_size == 0 {
+            return 0;
+        }
+        self.buffer_fullness * 100 / self.buffer_size
+    }
+}
@@ -0,0 +1,168 @@
+//! Optional self-test during driver initialization, enabled with `VAVK_SELF_TEST=1`.
+//!
+//! When the Vulkan video stack is broken (missing firmware, mismatched Mesa build, a driver that
+//! advertises video extensions it can't back), applications only report that "hardware decoding
+//! failed". The self-test creates a tiny video session on the selected device and binds its memory,
+//! so packagers and users get a clear diagnostic from `vainfo` instead.
+
+use std::{ptr, sync::PoisonError, time::Instant};
+
+use ash::{khr, prelude::*, vk};
+use log::{info, warn};
+use va_backend_sys::VAProfile;
+
+use crate::{
+    Codec, Operation, PartialVideoProfileInfo, VaError, VulkanData, codec_enabled,
+    session_memory::SessionMemory, video_caps::VideoCapabilities,
+    vk_video_profile_info_for_va_profile,
+};
+
+const SELF_TEST_ENV: &str = "VAVK_SELF_TEST";
+
+/// Profiles tried for the test session, in order. H.264 is the most widely supported codec.
+const PROFILES: [VAProfile; 3] = [
+    va_backend_sys::VAProfile_VAProfileH264High,
+    va_backend_sys::VAProfile_VAProfileH264Main,
+    va_backend_sys::VAProfile_VAProfileH264ConstrainedBaseline,
+];
+
+/// Whether the self-test was requested through [`SELF_TEST_ENV`].
+pub(crate) fn enabled() -> bool {
+    std::env::var_os(SELF_TEST_ENV).is_some_and(|value| value == "1")
+}
+
+/// Turns a Vulkan error into a [`VaError`] naming `step` as the failing step.
+fn failed(step: &'static str) -> impl FnOnce(vk::Result) -> VaError {
+    move |err| VaError::from(err).context(format!("self-test failed to {step}"))
+}
+
+/// Runs the self-test on the device of `vulkan`.
+// TODO: Also decode a built-in 16x16 IDR frame and check its query result status once the decode
+// path exists.
+pub(crate) fn run(vulkan: &VulkanData) -> Result<(), VaError> {
+    let start = Instant::now();
+
+    if !codec_enabled(Codec::H264, Operation::Decode) {
+        warn!("Skipping self-test, the driver was built without H.264 support");
+        return Ok(());
+    }
+    if !vulkan.supported_codecs.h264_decode {
+        warn!("Skipping self-test, H.264 decoding is not supported");
+        return Ok(());
+    }
+
+    let Some((profile, capabilities)) = PROFILES.into_iter().find_map(|profile| {
+        vulkan
+            .decode_capabilities(profile)
+            .map(|capabilities| (profile, capabilities))
+    }) else {
+        warn!("Skipping self-test, H.264 decoding is not supported");
+        return Ok(());
+    };
+
+    let device = create_device(vulkan).map_err(failed("create a device with a decode queue"))?;
+    let result = unsafe { test_session(vulkan, &device, profile, capabilities) };
+    unsafe { device.destroy_device(None) };
+    result?;
+
+    info!(
+        "Self-test passed for profile {profile} in {:?}",
+        start.elapsed()
+    );
+    Ok(())
+}
+
+fn create_device(vulkan: &VulkanData) -> VkResult<ash::Device> {
+    let queue_priorities = [1.0];
+    let queue_create_infos = [vk::DeviceQueueCreateInfo::default()
+        .queue_family_index(vulkan.decode_queue_family.index as u32)
+        .queue_priorities(&queue_priorities)];
+    let extension_names = [
+        khr::video_queue::NAME.as_ptr(),
+        khr::video_decode_queue::NAME.as_ptr(),
+        khr::video_decode_h264::NAME.as_ptr(),
+    ];
+    let create_info = vk::DeviceCreateInfo::default()
+        .queue_create_infos(&queue_create_infos)
+        .enabled_extension_names(&extension_names);
+
+    unsafe {
+        vulkan
+            .instance
+            .create_device(vulkan.physical_device, &create_info, None)
+    }
+}
+
+/// Creates a video session for `profile`, binds memory to it, and destroys it again.
+///
+/// # Safety
+/// `device` must have been created by [`create_device`] for `vulkan`.
+unsafe fn test_session(
+    vulkan: &VulkanData,
+    device: &ash::Device,
+    profile: VAProfile,
+    capabilities: &VideoCapabilities,
+) -> Result<(), VaError> {
+    let Some(PartialVideoProfileInfo::H264Decode { std_profile_idc }) =
+        vk_video_profile_info_for_va_profile(profile)
+    else {
+        unreachable!("self-test profiles are H.264 profiles");
+    };
+
+    let mut h264_profile_info = vk::VideoDecodeH264ProfileInfoKHR::default()
+        .std_profile_idc(std_profile_idc)
+        .picture_layout(vk::VideoDecodeH264PictureLayoutFlagsKHR::PROGRESSIVE);
+    let profile_info = vk::VideoProfileInfoKHR::default()
+        .video_codec_operation(vk::VideoCodecOperationFlagsKHR::DECODE_H264)
+        .chroma_subsampling(vk::VideoChromaSubsamplingFlagsKHR::TYPE_420)
+        .luma_bit_depth(vk::VideoComponentBitDepthFlagsKHR::TYPE_8)
+        .chroma_bit_depth(vk::VideoComponentBitDepthFlagsKHR::TYPE_8)
+        .push_next(&mut h264_profile_info);
+
+    // NV12 is supported for 8-bit 4:2:0 decoding by all current implementations
+    let format = vk::Format::G8_B8R8_2PLANE_420_UNORM;
+    let create_info = vk::VideoSessionCreateInfoKHR::default()
+        .queue_family_index(vulkan.decode_queue_family.index as u32)
+        .video_profile(&profile_info)
+        .picture_format(format)
+        .max_coded_extent(capabilities.coded_extent(16, 16)?)
+        .reference_picture_format(format)
+        .max_dpb_slots(capabilities.max_dpb_slots.min(1))
+        .max_active_reference_pictures(0)
+        .std_header_version(&capabilities.std_header_version);
+
+    let video_queue = khr::video_queue::Device::new(&vulkan.instance, device);
+    let mut budget = vulkan
+        .memory_budget
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    let mut session = vk::VideoSessionKHR::null();
+    unsafe {
+        (video_queue.fp().create_video_session_khr)(
+            device.handle(),
+            &create_info,
+            ptr::null(),
+            &mut session,
+        )
+    }
+    .result()
+    .map_err(failed("create a video session"))?;
+
+    let mut session_memory = SessionMemory::default();
+    let result = unsafe {
+        session_memory.bind(
+            &vulkan.instance,
+            vulkan.physical_device,
+            device,
+            &video_queue,
+            session,
+            Some(&mut budget),
+        )
+    };
+
+    unsafe {
+        (video_queue.fp().destroy_video_session_khr)(device.handle(), session, ptr::null());
+    }
+    unsafe { session_memory.free(device, Some(&mut budget)) };
+    result
+}
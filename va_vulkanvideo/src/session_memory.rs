@@ -0,0 +1,248 @@
+//! Memory bound to video sessions.
+//!
+//! A video session needs memory bound to each of the bind indices reported by
+//! vkGetVideoSessionMemoryRequirementsKHR before it can be used. Getting this wrong (a memory type
+//! the index doesn't accept, an index left unbound) usually isn't reported by the implementation
+//! until the first decode fails or the device is lost, so the requirements are validated here and
+//! failures name the bind index involved.
+
+use std::ptr;
+
+use ash::{khr, vk};
+use log::{debug, error};
+
+use crate::{VaError, memory_budget::MemoryBudget};
+
+#[derive(Debug)]
+struct Allocation {
+    memory_bind_index: u32,
+    memory: vk::DeviceMemory,
+    heap_index: u32,
+    size: vk::DeviceSize,
+}
+
+/// The memory bound to a video session.
+#[derive(Debug, Default)]
+pub(crate) struct SessionMemory {
+    allocations: Vec<Allocation>,
+}
+
+/// Picks a memory type allowed by `memory_type_bits`, preferring device-local ones, and returns
+/// it with its heap.
+fn memory_type(
+    memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    memory_type_bits: u32,
+) -> Option<(u32, u32)> {
+    let allowed = || {
+        memory_properties
+            .memory_types_as_slice()
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| memory_type_bits & (1 << i) != 0)
+    };
+    allowed()
+        .find(|(_, memory_type)| {
+            memory_type
+                .property_flags
+                .contains(vk::MemoryPropertyFlags::DEVICE_LOCAL)
+        })
+        .or_else(|| allowed().next())
+        .map(|(i, memory_type)| (i as u32, memory_type.heap_index))
+}
+
+fn query_requirements(
+    device: &ash::Device,
+    video_queue: &khr::video_queue::Device,
+    session: vk::VideoSessionKHR,
+) -> Result<Vec<vk::VideoSessionMemoryRequirementsKHR<'static>>, VaError> {
+    let vk_err =
+        |err| VaError::from(err).context("failed to query the video session memory requirements");
+    let get_requirements = video_queue.fp().get_video_session_memory_requirements_khr;
+    let mut count = 0;
+    unsafe { get_requirements(device.handle(), session, &mut count, ptr::null_mut()) }
+        .result()
+        .map_err(vk_err)?;
+    let mut requirements = vec![vk::VideoSessionMemoryRequirementsKHR::default(); count as usize];
+    unsafe {
+        get_requirements(
+            device.handle(),
+            session,
+            &mut count,
+            requirements.as_mut_ptr(),
+        )
+    }
+    .result()
+    .map_err(vk_err)?;
+    requirements.truncate(count as usize);
+    Ok(requirements)
+}
+
+impl SessionMemory {
+    /// Allocates memory for each bind index of `session` and binds it, accounting the
+    /// allocations in `budget` if given.
+    ///
+    /// On failure, the allocations made so far are kept in `self`: they may already be bound, so
+    /// they can only be freed with [`SessionMemory::free`] once the session has been destroyed.
+    ///
+    /// Video sessions can't use dedicated allocations (VkMemoryDedicatedAllocateInfo only names
+    /// images and buffers), but each bind index gets an allocation of its own, which is what
+    /// implementations expect. Indices are bound one at a time, so a failure can be attributed
+    /// to the index that caused it.
+    ///
+    /// # Safety
+    /// `session` must be a video session created on `device`, without memory bound yet, and
+    /// `self` must be empty.
+    pub(crate) unsafe fn bind(
+        &mut self,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &ash::Device,
+        video_queue: &khr::video_queue::Device,
+        session: vk::VideoSessionKHR,
+        mut budget: Option<&mut MemoryBudget>,
+    ) -> Result<(), VaError> {
+        let requirements = query_requirements(device, video_queue, session)?;
+        let memory_properties =
+            unsafe { instance.get_physical_device_memory_properties(physical_device) };
+
+        for requirement in &requirements {
+            unsafe {
+                self.bind_index(
+                    instance,
+                    physical_device,
+                    device,
+                    video_queue,
+                    session,
+                    &memory_properties,
+                    requirement,
+                    budget.as_deref_mut(),
+                )?
+            };
+        }
+
+        debug!(
+            "Bound {} bytes of video session memory to {} bind indices",
+            self.size(),
+            requirements.len()
+        );
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    unsafe fn bind_index(
+        &mut self,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &ash::Device,
+        video_queue: &khr::video_queue::Device,
+        session: vk::VideoSessionKHR,
+        memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        requirement: &vk::VideoSessionMemoryRequirementsKHR,
+        budget: Option<&mut MemoryBudget>,
+    ) -> Result<(), VaError> {
+        let index = requirement.memory_bind_index;
+        let size = requirement.memory_requirements.size;
+        if self
+            .allocations
+            .iter()
+            .any(|allocation| allocation.memory_bind_index == index)
+        {
+            return Err(VaError::OperationFailed.context(format!(
+                "video session memory bind index {index} reported twice"
+            )));
+        }
+
+        let Some((memory_type_index, heap_index)) = memory_type(
+            memory_properties,
+            requirement.memory_requirements.memory_type_bits,
+        ) else {
+            error!("No memory type for video session memory bind index {index}: {requirement:?}");
+            return Err(VaError::AllocationFailed
+                .context(format!("no memory type for session bind index {index}")));
+        };
+
+        // A session asking for more than the whole heap means the session parameters (usually
+        // the max coded extent or DPB slots) are way off. Fail clearly instead of letting the
+        // allocation fail or, worse, succeed by oversubscribing the heap.
+        let heap_size = memory_properties.memory_heaps[heap_index as usize].size;
+        if size == 0 || size > heap_size {
+            error!(
+                "Video session memory bind index {index} requires {size} bytes, but heap \
+                 {heap_index} has {heap_size}"
+            );
+            return Err(VaError::AllocationFailed
+                .context(format!("invalid size for session bind index {index}")));
+        }
+
+        let mut budget = budget;
+        if let Some(budget) = budget.as_deref_mut() {
+            budget.reserve(instance, physical_device, heap_index, size)?;
+        }
+
+        let allocate_info = vk::MemoryAllocateInfo::default()
+            .allocation_size(size)
+            .memory_type_index(memory_type_index);
+        let memory = match unsafe { device.allocate_memory(&allocate_info, None) } {
+            Ok(memory) => memory,
+            Err(err) => {
+                if let Some(budget) = budget {
+                    budget.release(heap_index, size);
+                }
+                return Err(VaError::from(err).context(format!(
+                    "failed to allocate {size} bytes for session bind index {index}"
+                )));
+            }
+        };
+        // Freed with the other allocations if binding fails
+        self.allocations.push(Allocation {
+            memory_bind_index: index,
+            memory,
+            heap_index,
+            size,
+        });
+
+        // Offset 0 satisfies any alignment requirement
+        let bind_info = vk::BindVideoSessionMemoryInfoKHR::default()
+            .memory_bind_index(index)
+            .memory(memory)
+            .memory_offset(0)
+            .memory_size(size);
+        unsafe {
+            (video_queue.fp().bind_video_session_memory_khr)(
+                device.handle(),
+                session,
+                1,
+                &bind_info,
+            )
+        }
+        .result()
+        .map_err(|err| {
+            error!(
+                "Failed to bind video session memory bind index {index} (memory type \
+                 {memory_type_index}, {size} bytes): {err}"
+            );
+            VaError::from(err).context(format!("failed to bind session bind index {index}"))
+        })
+    }
+
+    /// Total size of the allocations.
+    pub(crate) fn size(&self) -> vk::DeviceSize {
+        self.allocations
+            .iter()
+            .map(|allocation| allocation.size)
+            .sum()
+    }
+
+    /// Frees the memory, releasing it from `budget` if it was accounted there.
+    ///
+    /// # Safety
+    /// The session the memory is bound to must have been destroyed.
+    pub(crate) unsafe fn free(self, device: &ash::Device, mut budget: Option<&mut MemoryBudget>) {
+        for allocation in self.allocations {
+            unsafe { device.free_memory(allocation.memory, None) };
+            if let Some(budget) = budget.as_deref_mut() {
+                budget.release(allocation.heap_index, allocation.size);
+            }
+        }
+    }
+}
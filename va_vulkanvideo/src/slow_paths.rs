@@ -0,0 +1,76 @@
+//! Warnings for known slow paths.
+//!
+//! Some client behavior forces the driver onto paths that are much slower than necessary, but
+//! still produce correct output, so nobody notices until they profile. Each slow path is warned
+//! about with a hint on how to avoid it. To keep logs readable when it happens every frame, the
+//! warning is repeated with a count only at power-of-two occurrences.
+
+// Not wired up until surfaces and buffers are implemented.
+#![allow(dead_code)]
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use log::warn;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum SlowPath {
+    /// A surface had to be allocated with linear tiling.
+    LinearSurface,
+    /// vaGetImage/vaPutImage converted formats on the CPU.
+    CpuCsc,
+    /// vaMapBuffer waited for the GPU to finish with the buffer.
+    BlockingMap,
+}
+
+impl SlowPath {
+    const ALL: [Self; 3] = [Self::LinearSurface, Self::CpuCsc, Self::BlockingMap];
+
+    fn description(self) -> &'static str {
+        match self {
+            Self::LinearSurface => "Allocated a surface with linear tiling",
+            Self::CpuCsc => "Converted an image format on the CPU",
+            Self::BlockingMap => "Blocked in vaMapBuffer until the GPU was done with the buffer",
+        }
+    }
+
+    fn hint(self) -> &'static str {
+        match self {
+            Self::LinearSurface => {
+                "import dma-bufs with a tiled DRM format modifier instead of \
+                 DRM_FORMAT_MOD_LINEAR where the consumer supports it"
+            }
+            Self::CpuCsc => {
+                "request an image format the surface already has, or leave the software CSC \
+                 config attribute at 0"
+            }
+            Self::BlockingMap => {
+                "call vaSyncSurface/vaSyncBuffer before mapping, or keep more coded buffers in \
+                 flight"
+            }
+        }
+    }
+}
+
+static COUNTS: [AtomicU64; SlowPath::ALL.len()] =
+    [const { AtomicU64::new(0) }; SlowPath::ALL.len()];
+
+/// Each path must be at its own index in [`SlowPath::ALL`], which indexes [`COUNTS`].
+const _: () = {
+    let mut i = 0;
+    while i < SlowPath::ALL.len() {
+        assert!(SlowPath::ALL[i] as usize == i);
+        i += 1;
+    }
+};
+
+/// Records that `path` was taken, warning on the 1st, 2nd, 4th, 8th, ... time.
+pub(crate) fn taken(path: SlowPath) {
+    let count = COUNTS[path as usize].fetch_add(1, Ordering::Relaxed) + 1;
+    if count.is_power_of_two() {
+        warn!(
+            "Slow path: {} ({count} times so far). To avoid it, {}",
+            path.description(),
+            path.hint(),
+        );
+    }
+}
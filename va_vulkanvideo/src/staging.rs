@@ -0,0 +1,562 @@
+//! Persistently mapped staging ring for slice data and image uploads.
+//!
+//! Allocating (and mapping) a staging buffer per upload costs syscalls and allocator time in the
+//! hot decode path. Instead, each context owns a ring buffer that stays mapped. Space is handed out
+//! front to back, and reclaimed once the submission timeline reports that the GPU is done with it.
+//!
+//! Rings of contexts that haven't been used for a while (a paused video in a background tab) are
+//! released with [`trim_idle`], and recreated on the next upload.
+
+// Not wired up until contexts submit work.
+#![allow(dead_code)]
+
+use std::{
+    collections::VecDeque,
+    ptr::NonNull,
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
+
+use ash::{prelude::*, vk};
+use log::{debug, error, warn};
+
+use crate::{VaError, memory_budget::MemoryBudget};
+
+/// Environment variable setting after how many seconds without uploads the staging memory of a
+/// context is released. 0 disables trimming, the default is 30 seconds.
+const IDLE_TRIM_ENV: &str = "VAVK_IDLE_TRIM_SECONDS";
+
+const DEFAULT_IDLE_TRIM: Duration = Duration::from_secs(30);
+
+static IDLE_TRIM: LazyLock<Option<Duration>> = LazyLock::new(|| {
+    let Ok(value) = std::env::var(IDLE_TRIM_ENV) else {
+        return Some(DEFAULT_IDLE_TRIM);
+    };
+    match value.trim().parse::<u64>() {
+        Ok(0) => None,
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(err) => {
+            warn!("Ignoring invalid {IDLE_TRIM_ENV}={value:?}: {err}");
+            Some(DEFAULT_IDLE_TRIM)
+        }
+    }
+});
+
+/// Bookkeeping of a ring of `capacity` bytes, independent of the Vulkan objects backing it.
+///
+/// Positions are monotonically increasing byte counts, the offset into the ring is the position
+/// modulo the capacity. Space between `tail` and `head` is in use.
+#[derive(Clone, Debug)]
+struct RingAllocator {
+    capacity: vk::DeviceSize,
+    head: vk::DeviceSize,
+    tail: vk::DeviceSize,
+    /// Position of `head` at each submission, with the timeline value that signals its
+    /// completion.
+    in_flight: VecDeque<(vk::DeviceSize, u64)>,
+    /// Position of `head` at the last submission.
+    submitted: vk::DeviceSize,
+    /// Largest number of bytes allocated for a single submission.
+    peak_submission: vk::DeviceSize,
+}
+
+impl RingAllocator {
+    /// `capacity` must be a power of two, so that aligning a position also aligns its offset.
+    fn new(capacity: vk::DeviceSize) -> Self {
+        assert!(capacity.is_power_of_two());
+        Self {
+            capacity,
+            head: 0,
+            tail: 0,
+            in_flight: VecDeque::new(),
+            submitted: 0,
+            peak_submission: 0,
+        }
+    }
+
+    /// Reserves `size` contiguous bytes aligned to `align` (a power of two), returning their
+    /// offset, or `None` if there isn't enough free space right now.
+    fn allocate(&mut self, size: vk::DeviceSize, align: vk::DeviceSize) -> Option<vk::DeviceSize> {
+        debug_assert!(align.is_power_of_two());
+        if size == 0 || size > self.capacity || align > self.capacity {
+            return None;
+        }
+
+        let mut start = self.head.next_multiple_of(align);
+        // Allocations never wrap around the end, the remainder is skipped instead.
+        let offset = start % self.capacity;
+        if offset + size > self.capacity {
+            start += self.capacity - offset;
+        }
+
+        if start + size - self.tail > self.capacity {
+            return None;
+        }
+
+        self.head = start + size;
+        Some(start % self.capacity)
+    }
+
+    /// Marks everything allocated since the last submission as in use until `timeline_value` is
+    /// reached.
+    fn submit(&mut self, timeline_value: u64) {
+        if self.head == self.submitted {
+            return;
+        }
+        self.in_flight.push_back((self.head, timeline_value));
+        self.peak_submission = self.peak_submission.max(self.head - self.submitted);
+        self.submitted = self.head;
+    }
+
+    /// Frees the space of all submissions up to `completed_value`.
+    fn retire(&mut self, completed_value: u64) {
+        while let Some(&(head, timeline_value)) = self.in_flight.front() {
+            if timeline_value > completed_value {
+                break;
+            }
+            self.tail = head;
+            self.in_flight.pop_front();
+        }
+    }
+
+    fn used(&self) -> vk::DeviceSize {
+        self.head - self.tail
+    }
+
+    fn is_idle(&self) -> bool {
+        self.head == self.tail
+    }
+
+    /// Size of the largest allocation with `align` that would currently succeed.
+    fn largest_free(&self, align: vk::DeviceSize) -> vk::DeviceSize {
+        let start = self.head.next_multiple_of(align);
+        let Some(free) = (self.capacity + self.tail).checked_sub(start) else {
+            return 0;
+        };
+
+        // Free space may be split into the end and the start of the ring.
+        let until_end = self.capacity - start % self.capacity;
+        if free <= until_end {
+            free
+        } else {
+            until_end.max(free - until_end)
+        }
+    }
+
+    /// Capacity to grow to, if single submissions use more than half of the ring.
+    ///
+    /// The ring then can't hold two frames at once, so the CPU waits on the GPU every frame.
+    /// Growing to four times the peak leaves headroom, so the ring doesn't grow again with every
+    /// slightly larger frame.
+    fn grow_target(&self) -> Option<vk::DeviceSize> {
+        (self.peak_submission > self.capacity / 2)
+            .then(|| (self.peak_submission * 4).next_power_of_two())
+    }
+}
+
+/// A host-visible buffer that stays mapped for its whole lifetime.
+pub(crate) struct StagingRing {
+    usage: vk::BufferUsageFlags,
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    heap_index: u32,
+    mapped: NonNull<u8>,
+    ring: RingAllocator,
+    /// Time of the last write, for [`trim_idle`].
+    last_used: Instant,
+}
+
+impl StagingRing {
+    /// Creates a ring of at least `capacity` bytes (rounded up to a power of two), accounting
+    /// the allocation in `budget`.
+    pub(crate) fn new(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &ash::Device,
+        budget: &mut MemoryBudget,
+        capacity: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+    ) -> Result<Self, VaError> {
+        let capacity = capacity.next_power_of_two();
+        // Offsets into the mapping are converted to usize. On 32-bit builds, the mapping would
+        // fail anyway long before this, but don't rely on the implementation for it.
+        if isize::try_from(capacity).is_err() {
+            error!("Staging ring of {capacity} bytes doesn't fit into the address space");
+            return Err(VaError::AllocationFailed);
+        }
+
+        let vk_err = |err: vk::Result| {
+            VaError::from(err).context(format!("failed to create staging ring of {capacity} bytes"))
+        };
+
+        let buffer_info = vk::BufferCreateInfo::default()
+            .size(capacity)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let buffer = unsafe { device.create_buffer(&buffer_info, None) }.map_err(vk_err)?;
+
+        let requirements = unsafe { device.get_buffer_memory_requirements(buffer) };
+        let memory_properties =
+            unsafe { instance.get_physical_device_memory_properties(physical_device) };
+        let Some((memory_type_index, heap_index)) =
+            host_visible_memory_type(&memory_properties, requirements.memory_type_bits)
+        else {
+            error!("No host-visible memory type for staging buffers");
+            unsafe { device.destroy_buffer(buffer, None) };
+            return Err(VaError::AllocationFailed);
+        };
+
+        if let Err(err) = budget.reserve(instance, physical_device, heap_index, requirements.size) {
+            unsafe { device.destroy_buffer(buffer, None) };
+            return Err(err);
+        }
+
+        let result =
+            unsafe { Self::allocate_and_map(device, buffer, &requirements, memory_type_index) };
+        let (memory, mapped) = match result {
+            Ok(allocation) => allocation,
+            Err(err) => {
+                budget.release(heap_index, requirements.size);
+                unsafe { device.destroy_buffer(buffer, None) };
+                return Err(vk_err(err));
+            }
+        };
+
+        debug!("Created staging ring of {capacity} bytes");
+        Ok(Self {
+            usage,
+            buffer,
+            memory,
+            heap_index,
+            mapped,
+            ring: RingAllocator::new(capacity),
+            last_used: Instant::now(),
+        })
+    }
+
+    unsafe fn allocate_and_map(
+        device: &ash::Device,
+        buffer: vk::Buffer,
+        requirements: &vk::MemoryRequirements,
+        memory_type_index: u32,
+    ) -> VkResult<(vk::DeviceMemory, NonNull<u8>)> {
+        let allocate_info = vk::MemoryAllocateInfo::default()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_type_index);
+        let memory = unsafe { device.allocate_memory(&allocate_info, None)? };
+
+        let mapped = unsafe {
+            device
+                .bind_buffer_memory(buffer, memory, 0)
+                .and_then(|()| device.map_memory(memory, 0, vk::WHOLE_SIZE, Default::default()))
+        };
+        match mapped.map(|ptr| NonNull::new(ptr.cast::<u8>())) {
+            Ok(Some(mapped)) => Ok((memory, mapped)),
+            Ok(None) => {
+                unsafe { device.free_memory(memory, None) };
+                Err(vk::Result::ERROR_MEMORY_MAP_FAILED)
+            }
+            Err(err) => {
+                unsafe { device.free_memory(memory, None) };
+                Err(err)
+            }
+        }
+    }
+
+    pub(crate) fn buffer(&self) -> vk::Buffer {
+        self.buffer
+    }
+
+    pub(crate) fn capacity(&self) -> vk::DeviceSize {
+        self.ring.capacity
+    }
+
+    /// Copies `data` into the ring, returning its offset in [`StagingRing::buffer`], or `None`
+    /// if there isn't enough free space until earlier submissions complete.
+    pub(crate) fn write(&mut self, data: &[u8], align: vk::DeviceSize) -> Option<vk::DeviceSize> {
+        let offset = self.ring.allocate(data.len() as vk::DeviceSize, align)?;
+        self.last_used = Instant::now();
+        // SAFETY: The allocator only hands out ranges inside the mapped buffer, and the GPU isn't
+        // using this range anymore since it was retired. The memory is host-coherent, so no flush
+        // is needed.
+        unsafe {
+            self.mapped
+                .as_ptr()
+                .add(offset as usize)
+                .copy_from_nonoverlapping(data.as_ptr(), data.len());
+        }
+        Some(offset)
+    }
+
+    /// Copies as much of `data` into the ring as currently fits, possibly split into several
+    /// chunks, returning the regions to copy into the destination buffer at `dst_offset`.
+    ///
+    /// A single frame of high-bitrate content can exceed the whole ring. If the returned regions
+    /// don't cover all of `data`, the caller has to submit the copies, wait for earlier submissions
+    /// and continue with the rest.
+    pub(crate) fn write_chunked(
+        &mut self,
+        data: &[u8],
+        align: vk::DeviceSize,
+        dst_offset: vk::DeviceSize,
+    ) -> Vec<vk::BufferCopy> {
+        let mut regions = Vec::new();
+        let mut written = 0;
+        while written < data.len() {
+            let free = self.ring.largest_free(align) as usize;
+            let len = free.min(data.len() - written);
+            if len == 0 {
+                break;
+            }
+
+            let offset = self
+                .write(&data[written..written + len], align)
+                .expect("largest_free returned a size that doesn't fit");
+            regions.push(
+                vk::BufferCopy::default()
+                    .src_offset(offset)
+                    .dst_offset(dst_offset + written as vk::DeviceSize)
+                    .size(len as vk::DeviceSize),
+            );
+            written += len;
+        }
+        regions
+    }
+
+    /// Replaces the ring with a larger one if submissions were too large for it, see
+    /// [`RingAllocator::grow_target`]. This is deferred until all submissions using the ring have
+    /// been retired.
+    pub(crate) fn grow_if_needed(
+        &mut self,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &ash::Device,
+        budget: &mut MemoryBudget,
+    ) -> Result<(), VaError> {
+        let Some(capacity) = self.ring.grow_target() else {
+            return Ok(());
+        };
+        if !self.ring.is_idle() {
+            return Ok(());
+        }
+
+        debug!(
+            "Growing staging ring from {} to {capacity} bytes",
+            self.ring.capacity
+        );
+        let grown = Self::new(
+            instance,
+            physical_device,
+            device,
+            budget,
+            capacity,
+            self.usage,
+        )?;
+        let old = std::mem::replace(self, grown);
+        // SAFETY: The ring is idle, so the GPU is done with the old buffer.
+        unsafe { old.destroy(device, budget) };
+        Ok(())
+    }
+
+    /// Marks everything written since the last call as used by the submission that signals
+    /// `timeline_value`.
+    pub(crate) fn submit(&mut self, timeline_value: u64) {
+        self.ring.submit(timeline_value);
+    }
+
+    /// Reclaims the space of all submissions up to `completed_value`.
+    pub(crate) fn retire(&mut self, completed_value: u64) {
+        self.ring.retire(completed_value);
+    }
+
+    /// Destroys the buffer. The GPU must be done with all submissions using it.
+    pub(crate) unsafe fn destroy(self, device: &ash::Device, budget: &mut MemoryBudget) {
+        let size = unsafe { device.get_buffer_memory_requirements(self.buffer) }.size;
+        unsafe {
+            device.unmap_memory(self.memory);
+            device.destroy_buffer(self.buffer, None);
+            device.free_memory(self.memory, None);
+        }
+        budget.release(self.heap_index, size);
+    }
+}
+
+/// Destroys `ring` if it has been idle for longer than [`IDLE_TRIM_ENV`], returning whether it
+/// was. The owner recreates it with [`StagingRing::new`] on the next upload.
+///
+/// Not called yet, as contexts don't own staging rings so far. There is no timer thread, so the
+/// plan is for the driver functions of a display to call this for all of its contexts, trimming a
+/// context with no activity of its own while others are active.
+// TODO: Also reset idle command pools with VK_COMMAND_POOL_RESET_RELEASE_RESOURCES_BIT, once
+// contexts own them.
+pub(crate) fn trim_idle(
+    ring: &mut Option<StagingRing>,
+    device: &ash::Device,
+    budget: &mut MemoryBudget,
+    now: Instant,
+) -> bool {
+    let Some(timeout) = *IDLE_TRIM else {
+        return false;
+    };
+    let Some(idle) = ring.take_if(|ring| {
+        ring.ring.is_idle() && now.saturating_duration_since(ring.last_used) >= timeout
+    }) else {
+        return false;
+    };
+
+    debug!(
+        "Releasing staging ring of {} bytes, unused for {timeout:?}",
+        idle.capacity()
+    );
+    // SAFETY: The ring is idle, so the GPU is done with it.
+    unsafe { idle.destroy(device, budget) };
+    true
+}
+
+/// Picks a host-visible, host-coherent memory type out of `memory_type_bits`, returning its index
+/// and heap index.
+fn host_visible_memory_type(
+    memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    memory_type_bits: u32,
+) -> Option<(u32, u32)> {
+    let required = vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+    memory_properties
+        .memory_types_as_slice()
+        .iter()
+        .enumerate()
+        .find(|&(i, memory_type)| {
+            memory_type_bits & (1 << i) != 0 && memory_type.property_flags.contains(required)
+        })
+        .map(|(i, memory_type)| (i as u32, memory_type.heap_index))
+}
+
+/// Device-local, host-visible heaps up to this size are the classic PCI BAR window. It is shared
+/// with everything else on the system that wants to write to VRAM directly, so only larger
+/// (resizable BAR or unified memory) heaps are used for direct mapping.
+const BAR_WINDOW_SIZE: vk::DeviceSize = 256 * 1024 * 1024;
+
+/// Picks a device-local memory type out of `memory_type_bits` that the host can map directly,
+/// returning its index and heap index, or `None` if the device has no resizable BAR.
+///
+/// Encode bitstream buffers placed there are written by the GPU and read by the client through
+/// the mapping returned from vaMapBuffer, without a copy through a staging buffer per frame.
+pub(crate) fn direct_mapped_memory_type(
+    memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    memory_type_bits: u32,
+) -> Option<(u32, u32)> {
+    let required = vk::MemoryPropertyFlags::DEVICE_LOCAL
+        | vk::MemoryPropertyFlags::HOST_VISIBLE
+        | vk::MemoryPropertyFlags::HOST_COHERENT;
+    let heaps = memory_properties.memory_heaps_as_slice();
+    memory_properties
+        .memory_types_as_slice()
+        .iter()
+        .enumerate()
+        .find(|&(i, memory_type)| {
+            memory_type_bits & (1 << i) != 0
+                && memory_type.property_flags.contains(required)
+                && heaps[memory_type.heap_index as usize].size > BAR_WINDOW_SIZE
+        })
+        .map(|(i, memory_type)| (i as u32, memory_type.heap_index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocate_aligned() {
+        let mut ring = RingAllocator::new(256);
+        assert_eq!(ring.allocate(10, 1), Some(0));
+        assert_eq!(ring.allocate(10, 16), Some(16));
+        assert_eq!(ring.allocate(1, 4), Some(28));
+        assert_eq!(ring.used(), 29);
+    }
+
+    #[test]
+    fn allocate_invalid_sizes() {
+        let mut ring = RingAllocator::new(256);
+        assert_eq!(ring.allocate(0, 1), None);
+        assert_eq!(ring.allocate(257, 1), None);
+        assert_eq!(ring.allocate(1, 512), None);
+        assert!(ring.is_idle());
+    }
+
+    #[test]
+    fn full_until_retired() {
+        let mut ring = RingAllocator::new(256);
+        assert_eq!(ring.allocate(200, 1), Some(0));
+        ring.submit(1);
+        assert_eq!(ring.allocate(100, 1), None);
+        ring.retire(0);
+        assert_eq!(ring.allocate(100, 1), None);
+        ring.retire(1);
+        assert!(ring.is_idle());
+        // Doesn't fit before the end, so it starts over at the beginning
+        assert_eq!(ring.allocate(100, 1), Some(0));
+        assert_eq!(ring.used(), 156);
+    }
+
+    #[test]
+    fn retire_in_order() {
+        let mut ring = RingAllocator::new(256);
+        ring.allocate(64, 1);
+        ring.submit(1);
+        ring.allocate(64, 1);
+        ring.submit(2);
+        // Submitting without new allocations doesn't add an entry
+        ring.submit(3);
+        ring.retire(1);
+        assert_eq!(ring.used(), 64);
+        ring.retire(3);
+        assert!(ring.is_idle());
+    }
+
+    /// Checks that `largest_free` is exactly the largest allocation that succeeds.
+    fn assert_largest_free(ring: &RingAllocator, align: vk::DeviceSize) {
+        let largest = ring.largest_free(align);
+        if largest > 0 {
+            let mut copy = ring.clone();
+            assert!(copy.allocate(largest, align).is_some());
+        }
+        let mut copy = ring.clone();
+        assert_eq!(copy.allocate(largest + 1, align), None);
+    }
+
+    #[test]
+    fn largest_free_matches_allocate() {
+        let mut ring = RingAllocator::new(256);
+        assert_eq!(ring.largest_free(1), 256);
+        assert_largest_free(&ring, 1);
+
+        ring.allocate(100, 1);
+        ring.submit(1);
+        assert_largest_free(&ring, 1);
+        assert_largest_free(&ring, 64);
+
+        // Free space is split into the end and the start of the ring
+        ring.allocate(120, 1);
+        ring.submit(2);
+        ring.retire(1);
+        assert_eq!(ring.largest_free(1), 100);
+        assert_largest_free(&ring, 1);
+        assert_largest_free(&ring, 16);
+
+        ring.allocate(100, 1);
+        assert_eq!(ring.largest_free(1), 0);
+        assert_largest_free(&ring, 1);
+    }
+
+    #[test]
+    fn grow_target() {
+        let mut ring = RingAllocator::new(256);
+        ring.allocate(128, 1);
+        ring.submit(1);
+        assert_eq!(ring.grow_target(), None);
+
+        let mut ring = RingAllocator::new(256);
+        ring.allocate(200, 1);
+        ring.submit(1);
+        assert_eq!(ring.grow_target(), Some(1024));
+    }
+}
@@ -0,0 +1,87 @@
+//! Batching of the command buffers of several pictures into one vkQueueSubmit2.
+//!
+//! Each submission is an ioctl and a round trip through the kernel scheduler. Offline transcodes
+//! of small resolutions decode thousands of pictures per second, where that overhead dominates.
+//! When the display settings allow it (see `DisplaySettings::max_batched_pictures`), complete
+//! pictures are held back and submitted together.
+
+// Not wired up until pictures are submitted.
+#![allow(dead_code)]
+
+use ash::{prelude::*, vk};
+
+pub(crate) struct SubmitBatch {
+    /// Number of pictures after which the batch is flushed. 1 disables batching.
+    max_pictures: usize,
+    command_buffers: Vec<vk::CommandBufferSubmitInfo<'static>>,
+    /// Timeline values of the first and the last picture of the batch. Only the last one is
+    /// signalled.
+    timeline_values: Option<(u64, u64)>,
+}
+
+impl SubmitBatch {
+    pub(crate) fn new(max_pictures: usize) -> Self {
+        Self {
+            max_pictures: max_pictures.max(1),
+            command_buffers: Vec::with_capacity(max_pictures),
+            timeline_values: None,
+        }
+    }
+
+    /// Adds the command buffer of a picture that signals `timeline_value` when done. Returns
+    /// whether the batch is full and has to be flushed.
+    pub(crate) fn push(&mut self, command_buffer: vk::CommandBuffer, timeline_value: u64) -> bool {
+        let first = match self.timeline_values {
+            Some((first, last)) => {
+                debug_assert!(last < timeline_value);
+                first
+            }
+            None => timeline_value,
+        };
+        self.command_buffers
+            .push(vk::CommandBufferSubmitInfo::default().command_buffer(command_buffer));
+        self.timeline_values = Some((first, timeline_value));
+        self.command_buffers.len() >= self.max_pictures
+    }
+
+    /// Whether the picture signalling `timeline_value` is still held back. Waiting for it (e.g.
+    /// in vaSyncSurface) requires flushing first, or it never completes.
+    pub(crate) fn is_pending(&self, timeline_value: u64) -> bool {
+        self.timeline_values
+            .is_some_and(|(first, last)| (first..=last).contains(&timeline_value))
+    }
+
+    /// Submits all held back pictures to `queue`. The command buffers execute in order, so only
+    /// the value of the last picture is signalled on `timeline`.
+    ///
+    /// If the submission fails, the pictures stay held back and [`Self::is_pending`] keeps
+    /// reporting them, so that nothing waits for a value that is never signalled. The flush can
+    /// be retried, or the context marked as failed.
+    ///
+    /// # Safety
+    /// The command buffers must be in the executable state and `timeline` must be a timeline
+    /// semaphore on `device`.
+    pub(crate) unsafe fn flush(
+        &mut self,
+        device: &ash::Device,
+        queue: vk::Queue,
+        timeline: vk::Semaphore,
+    ) -> VkResult<()> {
+        let Some((_, signal_value)) = self.timeline_values else {
+            return Ok(());
+        };
+
+        let signal_infos = [vk::SemaphoreSubmitInfo::default()
+            .semaphore(timeline)
+            .value(signal_value)
+            .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)];
+        let submit_info = vk::SubmitInfo2::default()
+            .command_buffer_infos(&self.command_buffers)
+            .signal_semaphore_infos(&signal_infos);
+
+        unsafe { device.queue_submit2(queue, &[submit_info], vk::Fence::null()) }?;
+        self.command_buffers.clear();
+        self.timeline_values = None;
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+//! Surface creation parameters, shared by vaCreateSurfaces2 and the legacy vaCreateSurfaces.
+
+use log::debug;
+use va_backend_sys::VASurfaceAttrib;
+
+use crate::{VaError, image_formats};
+
+/// Pixel format surfaces of `rt_format` get if the client doesn't ask for one.
+pub(crate) const fn default_fourcc(rt_format: u32) -> Option<u32> {
+    match rt_format {
+        va_backend_sys::VA_RT_FORMAT_YUV420 => Some(va_backend_sys::VA_FOURCC_NV12),
+        va_backend_sys::VA_RT_FORMAT_YUV420_10 => Some(va_backend_sys::VA_FOURCC_P010),
+        va_backend_sys::VA_RT_FORMAT_RGB32 => Some(va_backend_sys::VA_FOURCC_BGRX),
+        _ => None,
+    }
+}
+
+/// Render target format of surfaces with `fourcc`.
+pub(crate) const fn rt_format(fourcc: u32) -> Option<u32> {
+    match fourcc {
+        va_backend_sys::VA_FOURCC_NV12 => Some(va_backend_sys::VA_RT_FORMAT_YUV420),
+        va_backend_sys::VA_FOURCC_P010 => Some(va_backend_sys::VA_RT_FORMAT_YUV420_10),
+        va_backend_sys::VA_FOURCC_BGRX => Some(va_backend_sys::VA_RT_FORMAT_RGB32),
+        _ => None,
+    }
+}
+
+/// Everything needed to allocate a batch of surfaces.
+#[derive(Debug, Clone)]
+#[allow(dead_code)] // Used once surfaces are allocated
+pub(crate) struct SurfaceRequest {
+    pub(crate) rt_format: u32,
+    pub(crate) fourcc: u32,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+}
+
+impl SurfaceRequest {
+    /// Validates the arguments of vaCreateSurfaces2.
+    pub(crate) fn new(
+        rt_format: u32,
+        width: u32,
+        height: u32,
+        attribs: &[VASurfaceAttrib],
+    ) -> Result<Self, VaError> {
+        if width == 0 || height == 0 {
+            return Err(VaError::ResolutionNotSupported
+                .context(format!("can't create {width}x{height} surfaces")));
+        }
+        let Some(mut fourcc) = default_fourcc(rt_format) else {
+            return Err(VaError::UnsupportedRtformat
+                .context(format!("can't create surfaces of RT format {rt_format:#x}")));
+        };
+
+        for attrib in attribs {
+            match attrib.type_ {
+                va_backend_sys::VASurfaceAttribType_VASurfaceAttribPixelFormat => {
+                    if attrib.value.type_
+                        != va_backend_sys::VAGenericValueType_VAGenericValueTypeInteger
+                    {
+                        return Err(VaError::InvalidParameter
+                            .context("pixel format attribute isn't an integer"));
+                    }
+                    // SAFETY: The type was checked above.
+                    fourcc = unsafe { attrib.value.value.i } as u32;
+                }
+                // TODO: Memory types other than VA, dma-buf import. GBM buffers imported by
+                // compositors (wlroots, Kodi GBM) need the full cycle: per-plane import with the
+                // given modifier (both Intel Y/CCS and AMD DCC style), decoding into them in
+                // place, and a sync file exported for the compositor to wait on.
+                // TODO: udmabuf/ION import for ARM boards. Those buffers come without a modifier
+                // (implicitly LINEAR) as VA_SURFACE_ATTRIB_MEM_TYPE_DRM_PRIME_2, and are imported
+                // with VK_EXT_external_memory_dma_buf. Only picking a render-only device without a
+                // primary node is implemented so far, see probe_physical_device.
+                // TODO: Honor the per-plane pitches and offsets of VASurfaceAttribExternalBuffers
+                // as given (V4L2 capture buffers often have padded or oddly aligned planes) via
+                // VkImageDrmFormatModifierExplicitCreateInfoEXT. If the implementation rejects the
+                // layout, import the dma-buf as a buffer instead and repack it into an internal
+                // image with a copy before each use.
+                va_backend_sys::VASurfaceAttribType_VASurfaceAttribMemoryType
+                | va_backend_sys::VASurfaceAttribType_VASurfaceAttribExternalBufferDescriptor => {
+                    return Err(VaError::UnsupportedMemoryType
+                        .context("only driver-allocated surfaces are supported"));
+                }
+                _ => debug!("Ignoring surface attribute {}", attrib.type_),
+            }
+        }
+
+        if image_formats::planes(fourcc).is_none() {
+            return Err(VaError::InvalidImageFormat
+                .context(format!("can't create surfaces with fourcc {fourcc:#x}")));
+        }
+
+        Ok(Self {
+            rt_format,
+            fourcc,
+            width,
+            height,
+        })
+    }
+
+    /// Maps the arguments of the legacy vaCreateSurfaces, which only takes a single
+    /// `VA_RT_FORMAT_*` value, to a request with the default pixel format for it.
+    pub(crate) fn legacy(format: i32, width: i32, height: i32) -> Result<Self, VaError> {
+        let (Ok(width), Ok(height)) = (u32::try_from(width), u32::try_from(height)) else {
+            return Err(VaError::ResolutionNotSupported
+                .context(format!("can't create {width}x{height} surfaces")));
+        };
+        Self::new(format as u32, width, height, &[])
+    }
+}
@@ -0,0 +1,39 @@
+//! Hooks run when the driver is unloaded or the process exits.
+//!
+//! libva calls vaTerminate for every display it initialized, but hosts don't always terminate
+//! their displays before exiting or unloading libva. Statics are never dropped, so anything that
+//! must happen before the driver goes away (joining worker threads, reporting leaked state) is
+//! registered here and run from the library's `.fini_array`.
+//!
+//! The library is linked with `-z nodelete` (see build.rs), so the code stays mapped after
+//! dlclose. Still, don't add `thread_local!`s with destructors: they would run on thread exit
+//! after the Vulkan instance may already be gone.
+
+use std::sync::{Mutex, PoisonError};
+
+use log::debug;
+
+static HOOKS: Mutex<Vec<fn()>> = Mutex::new(Vec::new());
+
+/// Registers `hook` to run at unload, after all hooks registered later.
+pub(crate) fn at_unload(hook: fn()) {
+    HOOKS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(hook);
+}
+
+extern "C" fn run_hooks() {
+    let hooks = std::mem::take(&mut *HOOKS.lock().unwrap_or_else(PoisonError::into_inner));
+    debug!("Running {} teardown hooks", hooks.len());
+    for hook in hooks.into_iter().rev() {
+        // Unwinding out of here would abort the process while it's exiting anyway.
+        if std::panic::catch_unwind(hook).is_err() {
+            debug!("Teardown hook panicked");
+        }
+    }
+}
+
+#[used]
+#[unsafe(link_section = ".fini_array")]
+static RUN_HOOKS: extern "C" fn() = run_hooks;
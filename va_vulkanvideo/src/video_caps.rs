@@ -0,0 +1,488 @@
+//! Video capabilities of the physical device, per VA profile.
+//!
+//! vkGetPhysicalDeviceVideoCapabilitiesKHR is queried once when probing the physical device. The
+//! limits are needed whenever surfaces or contexts are created, and coded extents outside of them
+//! are undefined behavior in Vulkan, so they have to be rejected up front.
+
+use ash::{khr, vk};
+use log::{debug, warn};
+use va_backend_sys::VAProfile;
+
+use crate::{PartialVideoProfileInfo, VaError, vk_video_profile_info_for_va_profile};
+
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct VideoCapabilities {
+    pub(crate) picture_access_granularity: vk::Extent2D,
+    pub(crate) min_coded_extent: vk::Extent2D,
+    pub(crate) max_coded_extent: vk::Extent2D,
+    pub(crate) max_dpb_slots: u32,
+    pub(crate) max_active_reference_pictures: u32,
+    /// Highest supported level, as the codec's std level enum (`StdVideoH264LevelIdc`,
+    /// `StdVideoH265LevelIdc` or `StdVideoAV1Level`). These are all in ascending order.
+    pub(crate) max_level: u32,
+    /// Whether the DPB and output pictures can coincide and/or be distinct images.
+    pub(crate) decode_flags: vk::VideoDecodeCapabilityFlagsKHR,
+    /// Version of the codec std headers, needed to create video sessions.
+    pub(crate) std_header_version: vk::ExtensionProperties,
+    /// VA fourcc of the format decoded pictures are output in.
+    pub(crate) output_fourcc: u32,
+}
+
+/// Encode capabilities for a VA profile, as far as they are exposed through config attributes.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct EncodeCapabilities {
+    pub(crate) max_coded_extent: vk::Extent2D,
+    /// Maximum number of L0 references of P pictures.
+    pub(crate) max_l0_references: u32,
+    /// Maximum number of L1 references of B pictures. 0 if B pictures can't have backward
+    /// references.
+    pub(crate) max_l1_references: u32,
+    /// Maximum number of slices (H.264) or slice segments (H.265) per picture.
+    pub(crate) max_slices: u32,
+    /// H.264 syntax elements the encoder can set, `None` for other codecs.
+    pub(crate) h264_std_flags: Option<vk::VideoEncodeH264StdFlagsKHR>,
+    pub(crate) rate_control_modes: vk::VideoEncodeRateControlModeFlagsKHR,
+}
+
+impl EncodeCapabilities {
+    /// Queries the encode capabilities for `va_profile`, returning `None` if it isn't an H.264 or
+    /// H.265 profile or can't be encoded on `physical_device`.
+    pub(crate) fn query(
+        video_queue: &khr::video_queue::Instance,
+        physical_device: vk::PhysicalDevice,
+        va_profile: VAProfile,
+    ) -> Option<Self> {
+        let partial_profile_info = vk_video_profile_info_for_va_profile(va_profile)?;
+        let (chroma_subsampling, bit_depth) = va_profile_format(va_profile);
+
+        let mut h264_profile_info = vk::VideoEncodeH264ProfileInfoKHR::default();
+        let mut h265_profile_info = vk::VideoEncodeH265ProfileInfoKHR::default();
+        let mut h264_capabilities = vk::VideoEncodeH264CapabilitiesKHR::default();
+        let mut h265_capabilities = vk::VideoEncodeH265CapabilitiesKHR::default();
+        let mut encode_capabilities = vk::VideoEncodeCapabilitiesKHR::default();
+
+        let profile_info = vk::VideoProfileInfoKHR::default()
+            .chroma_subsampling(chroma_subsampling)
+            .luma_bit_depth(bit_depth)
+            .chroma_bit_depth(bit_depth);
+        let capabilities = vk::VideoCapabilitiesKHR::default().push_next(&mut encode_capabilities);
+
+        // The profile_idc is the same for decode and encode
+        let (profile_info, mut capabilities) = match partial_profile_info {
+            PartialVideoProfileInfo::H264Decode { std_profile_idc } => {
+                h264_profile_info.std_profile_idc = std_profile_idc;
+                (
+                    profile_info
+                        .video_codec_operation(vk::VideoCodecOperationFlagsKHR::ENCODE_H264)
+                        .push_next(&mut h264_profile_info),
+                    capabilities.push_next(&mut h264_capabilities),
+                )
+            }
+            PartialVideoProfileInfo::H265Decode { std_profile_idc } => {
+                h265_profile_info.std_profile_idc = std_profile_idc;
+                (
+                    profile_info
+                        .video_codec_operation(vk::VideoCodecOperationFlagsKHR::ENCODE_H265)
+                        .push_next(&mut h265_profile_info),
+                    capabilities.push_next(&mut h265_capabilities),
+                )
+            }
+            PartialVideoProfileInfo::Av1Decode { .. } => return None,
+        };
+
+        let result = unsafe {
+            (video_queue.fp().get_physical_device_video_capabilities_khr)(
+                physical_device,
+                &profile_info,
+                &mut capabilities,
+            )
+        };
+        if result != vk::Result::SUCCESS {
+            debug!("Profile {va_profile} is not supported for encoding: {result:?}");
+            return None;
+        }
+
+        let max_coded_extent = capabilities.max_coded_extent;
+        // The per-list limits can exceed what the DPB can hold at once
+        let max_active = capabilities.max_active_reference_pictures;
+        let (max_l0_references, max_l1_references, max_slices, h264_std_flags) =
+            if profile_info.video_codec_operation == vk::VideoCodecOperationFlagsKHR::ENCODE_H264 {
+                (
+                    h264_capabilities.max_p_picture_l0_reference_count,
+                    h264_capabilities.max_l1_reference_count,
+                    h264_capabilities.max_slice_count,
+                    Some(h264_capabilities.std_syntax_flags),
+                )
+            } else {
+                (
+                    h265_capabilities.max_p_picture_l0_reference_count,
+                    h265_capabilities.max_l1_reference_count,
+                    h265_capabilities.max_slice_segment_count,
+                    None,
+                )
+            };
+        let capabilities = Self {
+            max_coded_extent,
+            max_l0_references: max_l0_references.min(max_active),
+            max_l1_references: max_l1_references.min(max_active),
+            max_slices,
+            h264_std_flags,
+            rate_control_modes: encode_capabilities.rate_control_modes,
+        };
+        debug!("Encode capabilities for profile {va_profile}: {capabilities:?}");
+        Some(capabilities)
+    }
+}
+
+/// Decode output formats we can hand out as surfaces, with their VA fourcc.
+const OUTPUT_FORMATS: [(vk::Format, u32); 2] = [
+    (
+        vk::Format::G8_B8R8_2PLANE_420_UNORM,
+        va_backend_sys::VA_FOURCC_NV12,
+    ),
+    (
+        vk::Format::G10X6_B10X6R10X6_2PLANE_420_UNORM_3PACK16,
+        va_backend_sys::VA_FOURCC_P010,
+    ),
+];
+
+/// Picks the first format in the implementation's order of preference that we can map to a VA
+/// fourcc, or `None` if there is none.
+fn query_output_fourcc(
+    video_queue: &khr::video_queue::Instance,
+    physical_device: vk::PhysicalDevice,
+    profile_info: &vk::VideoProfileInfoKHR,
+) -> Option<u32> {
+    let mut profile_list =
+        vk::VideoProfileListInfoKHR::default().profiles(std::slice::from_ref(profile_info));
+    let format_info = vk::PhysicalDeviceVideoFormatInfoKHR::default()
+        .image_usage(vk::ImageUsageFlags::VIDEO_DECODE_DST_KHR)
+        .push_next(&mut profile_list);
+
+    let get_format_properties = video_queue
+        .fp()
+        .get_physical_device_video_format_properties_khr;
+    let mut count = 0;
+    let result = unsafe {
+        get_format_properties(
+            physical_device,
+            &format_info,
+            &mut count,
+            std::ptr::null_mut(),
+        )
+    };
+    if result != vk::Result::SUCCESS {
+        debug!("Failed to query video format properties: {result:?}");
+        return None;
+    }
+    let mut properties = vec![vk::VideoFormatPropertiesKHR::default(); count as usize];
+    let result = unsafe {
+        get_format_properties(
+            physical_device,
+            &format_info,
+            &mut count,
+            properties.as_mut_ptr(),
+        )
+    };
+    if result != vk::Result::SUCCESS {
+        debug!("Failed to query video format properties: {result:?}");
+        return None;
+    }
+    properties.truncate(count as usize);
+
+    properties.iter().find_map(|properties| {
+        OUTPUT_FORMATS
+            .iter()
+            .find(|(format, _)| *format == properties.format)
+            .map(|(_, fourcc)| *fourcc)
+    })
+}
+
+/// Chroma subsampling and bit depth of the pictures of a VA profile.
+fn va_profile_format(
+    va_profile: VAProfile,
+) -> (
+    vk::VideoChromaSubsamplingFlagsKHR,
+    vk::VideoComponentBitDepthFlagsKHR,
+) {
+    match va_profile {
+        va_backend_sys::VAProfile_VAProfileHEVCMain10 => (
+            vk::VideoChromaSubsamplingFlagsKHR::TYPE_420,
+            vk::VideoComponentBitDepthFlagsKHR::TYPE_10,
+        ),
+        // AV1 High is the 4:4:4 profile
+        va_backend_sys::VAProfile_VAProfileAV1Profile1 => (
+            vk::VideoChromaSubsamplingFlagsKHR::TYPE_444,
+            vk::VideoComponentBitDepthFlagsKHR::TYPE_8,
+        ),
+        _ => (
+            vk::VideoChromaSubsamplingFlagsKHR::TYPE_420,
+            vk::VideoComponentBitDepthFlagsKHR::TYPE_8,
+        ),
+    }
+}
+
+impl VideoCapabilities {
+    /// Queries the decode capabilities for `va_profile`, returning `None` if the profile can't be
+    /// decoded on `physical_device`, or if none of its output formats can be used for surfaces.
+    pub(crate) fn query_decode(
+        video_queue: &khr::video_queue::Instance,
+        physical_device: vk::PhysicalDevice,
+        va_profile: VAProfile,
+    ) -> Option<Self> {
+        let partial_profile_info = vk_video_profile_info_for_va_profile(va_profile)?;
+        let (chroma_subsampling, bit_depth) = va_profile_format(va_profile);
+
+        let mut h264_profile_info = vk::VideoDecodeH264ProfileInfoKHR::default()
+            .picture_layout(vk::VideoDecodeH264PictureLayoutFlagsKHR::PROGRESSIVE);
+        let mut h265_profile_info = vk::VideoDecodeH265ProfileInfoKHR::default();
+        let mut av1_profile_info = vk::VideoDecodeAV1ProfileInfoKHR::default();
+        let mut h264_capabilities = vk::VideoDecodeH264CapabilitiesKHR::default();
+        let mut h265_capabilities = vk::VideoDecodeH265CapabilitiesKHR::default();
+        let mut av1_capabilities = vk::VideoDecodeAV1CapabilitiesKHR::default();
+        let mut decode_capabilities = vk::VideoDecodeCapabilitiesKHR::default();
+
+        let profile_info = vk::VideoProfileInfoKHR::default()
+            .chroma_subsampling(chroma_subsampling)
+            .luma_bit_depth(bit_depth)
+            .chroma_bit_depth(bit_depth);
+        let capabilities = vk::VideoCapabilitiesKHR::default().push_next(&mut decode_capabilities);
+
+        // The codec-specific capabilities structure is required by the spec.
+        let (profile_info, mut capabilities) = match partial_profile_info {
+            PartialVideoProfileInfo::H264Decode { std_profile_idc } => {
+                h264_profile_info.std_profile_idc = std_profile_idc;
+                (
+                    profile_info
+                        .video_codec_operation(vk::VideoCodecOperationFlagsKHR::DECODE_H264)
+                        .push_next(&mut h264_profile_info),
+                    capabilities.push_next(&mut h264_capabilities),
+                )
+            }
+            PartialVideoProfileInfo::H265Decode { std_profile_idc } => {
+                h265_profile_info.std_profile_idc = std_profile_idc;
+                (
+                    profile_info
+                        .video_codec_operation(vk::VideoCodecOperationFlagsKHR::DECODE_H265)
+                        .push_next(&mut h265_profile_info),
+                    capabilities.push_next(&mut h265_capabilities),
+                )
+            }
+            PartialVideoProfileInfo::Av1Decode { std_profile } => {
+                av1_profile_info.std_profile = std_profile;
+                (
+                    profile_info
+                        .video_codec_operation(vk::VideoCodecOperationFlagsKHR::DECODE_AV1)
+                        .push_next(&mut av1_profile_info),
+                    capabilities.push_next(&mut av1_capabilities),
+                )
+            }
+        };
+
+        let result = unsafe {
+            (video_queue.fp().get_physical_device_video_capabilities_khr)(
+                physical_device,
+                &profile_info,
+                &mut capabilities,
+            )
+        };
+        if result != vk::Result::SUCCESS {
+            debug!("Profile {va_profile} is not supported for decoding: {result:?}");
+            return None;
+        }
+
+        let mut capabilities = Self {
+            picture_access_granularity: capabilities.picture_access_granularity,
+            min_coded_extent: capabilities.min_coded_extent,
+            max_coded_extent: capabilities.max_coded_extent,
+            max_dpb_slots: capabilities.max_dpb_slots,
+            max_active_reference_pictures: capabilities.max_active_reference_pictures,
+            max_level: 0,
+            std_header_version: capabilities.std_header_version,
+            decode_flags: decode_capabilities.flags,
+            output_fourcc: 0,
+        };
+        capabilities.max_level = match profile_info.video_codec_operation {
+            vk::VideoCodecOperationFlagsKHR::DECODE_H264 => h264_capabilities.max_level_idc,
+            vk::VideoCodecOperationFlagsKHR::DECODE_H265 => h265_capabilities.max_level_idc,
+            _ => av1_capabilities.max_level,
+        };
+
+        let Some(output_fourcc) = query_output_fourcc(video_queue, physical_device, &profile_info)
+        else {
+            debug!("Profile {va_profile} has no decode output format usable for surfaces");
+            return None;
+        };
+        capabilities.output_fourcc = output_fourcc;
+        debug!("Decode capabilities for profile {va_profile}: {capabilities:?}");
+        Some(capabilities)
+    }
+
+    /// Checks that a stream with `num_references` active reference pictures and `level` (in the
+    /// representation of [`Self::max_level`]) is within the limits of the implementation.
+    ///
+    /// Exceeding them is undefined behavior in Vulkan, which in practice means corrupted output
+    /// or device loss, so such streams are rejected with [`VaError::UnsupportedProfile`] when the
+    /// session is created, and pictures with too many references before they are decoded.
+    #[allow(dead_code)] // Used once sessions are created
+    pub(crate) fn check_stream_limits(
+        &self,
+        num_references: u32,
+        level: Option<u32>,
+    ) -> Result<(), VaError> {
+        if num_references > self.max_active_reference_pictures
+            || num_references >= self.max_dpb_slots
+        {
+            warn!(
+                "Stream uses {num_references} reference pictures, but only {} active references \
+                 and {} DPB slots are supported",
+                self.max_active_reference_pictures, self.max_dpb_slots,
+            );
+            return Err(VaError::UnsupportedProfile
+                .context(format!("too many reference pictures ({num_references})")));
+        }
+        if let Some(level) = level
+            && level > self.max_level
+        {
+            warn!(
+                "Stream level (std enum {level}) exceeds the maximum supported level (std enum {})",
+                self.max_level,
+            );
+            return Err(VaError::UnsupportedProfile.context("level not supported"));
+        }
+        Ok(())
+    }
+
+    /// Returns the coded extent for pictures of `width`x`height`.
+    ///
+    /// Streams with odd dimensions (e.g. 1920x1080 with 16x16 macroblocks, or 8K with a 64x64
+    /// granularity) are padded to the picture access granularity, and tiny pictures to the minimum
+    /// coded extent. Pictures larger than the maximum coded extent are rejected with
+    /// [`VaError::ResolutionNotSupported`].
+    pub(crate) fn coded_extent(&self, width: u32, height: u32) -> Result<vk::Extent2D, VaError> {
+        let align = |value: u32, granularity: u32, min: u32| -> Option<u32> {
+            value.max(min).checked_next_multiple_of(granularity.max(1))
+        };
+
+        let extent = align(
+            width,
+            self.picture_access_granularity.width,
+            self.min_coded_extent.width,
+        )
+        .zip(align(
+            height,
+            self.picture_access_granularity.height,
+            self.min_coded_extent.height,
+        ))
+        .map(|(width, height)| vk::Extent2D { width, height });
+
+        let Some(extent) = extent.filter(|extent| {
+            extent.width <= self.max_coded_extent.width
+                && extent.height <= self.max_coded_extent.height
+        }) else {
+            warn!(
+                "Resolution {width}x{height} exceeds the maximum coded extent {}x{}",
+                self.max_coded_extent.width, self.max_coded_extent.height,
+            );
+            return Err(VaError::ResolutionNotSupported);
+        };
+
+        Ok(extent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use va_backend_sys::VAStatus;
+
+    use super::*;
+
+    fn capabilities(granularity: u32, min: u32, max: u32) -> VideoCapabilities {
+        VideoCapabilities {
+            picture_access_granularity: vk::Extent2D {
+                width: granularity,
+                height: granularity,
+            },
+            min_coded_extent: vk::Extent2D {
+                width: min,
+                height: min,
+            },
+            max_coded_extent: vk::Extent2D {
+                width: max,
+                height: max,
+            },
+            max_dpb_slots: 17,
+            max_active_reference_pictures: 16,
+            max_level: 0,
+            decode_flags: vk::VideoDecodeCapabilityFlagsKHR::empty(),
+            std_header_version: vk::ExtensionProperties::default(),
+            output_fourcc: 0,
+        }
+    }
+
+    fn coded_extent(
+        capabilities: &VideoCapabilities,
+        width: u32,
+        height: u32,
+    ) -> Result<(u32, u32), VAStatus> {
+        capabilities
+            .coded_extent(width, height)
+            .map(|extent| (extent.width, extent.height))
+            .map_err(VAStatus::from)
+    }
+
+    #[test]
+    fn rounded_to_granularity() {
+        let capabilities = capabilities(16, 16, 4096);
+        assert_eq!(coded_extent(&capabilities, 1920, 1080), Ok((1920, 1088)));
+        assert_eq!(coded_extent(&capabilities, 1921, 1), Ok((1936, 16)));
+        assert_eq!(coded_extent(&capabilities, 1280, 720), Ok((1280, 720)));
+    }
+
+    #[test]
+    fn rounded_to_large_granularity() {
+        let capabilities = capabilities(64, 64, 8192);
+        assert_eq!(coded_extent(&capabilities, 7680, 4320), Ok((7680, 4352)));
+        assert_eq!(coded_extent(&capabilities, 1920, 1080), Ok((1920, 1088)));
+    }
+
+    #[test]
+    fn zero_granularity() {
+        let capabilities = capabilities(0, 1, 4096);
+        assert_eq!(coded_extent(&capabilities, 1919, 1079), Ok((1919, 1079)));
+    }
+
+    #[test]
+    fn padded_to_min_coded_extent() {
+        let capabilities = capabilities(16, 48, 4096);
+        assert_eq!(coded_extent(&capabilities, 16, 16), Ok((48, 48)));
+        assert_eq!(coded_extent(&capabilities, 0, 0), Ok((48, 48)));
+        assert_eq!(coded_extent(&capabilities, 17, 64), Ok((48, 64)));
+    }
+
+    #[test]
+    fn above_max_coded_extent() {
+        let capabilities = capabilities(16, 16, 4096);
+        let unsupported = Err(VaError::ResolutionNotSupported.into());
+        assert_eq!(coded_extent(&capabilities, 4096, 4096), Ok((4096, 4096)));
+        assert_eq!(coded_extent(&capabilities, 4097, 16), unsupported);
+        assert_eq!(coded_extent(&capabilities, 16, 4097), unsupported);
+    }
+
+    #[test]
+    fn padded_above_max_coded_extent() {
+        let capabilities = capabilities(16, 16, 4088);
+        assert_eq!(
+            coded_extent(&capabilities, 4081, 16),
+            Err(VaError::ResolutionNotSupported.into())
+        );
+    }
+
+    #[test]
+    fn overflow() {
+        let capabilities = capabilities(16, 16, u32::MAX);
+        let unsupported = Err(VaError::ResolutionNotSupported.into());
+        assert_eq!(coded_extent(&capabilities, u32::MAX, 16), unsupported);
+        assert_eq!(coded_extent(&capabilities, 16, u32::MAX), unsupported);
+    }
+}
@@ -0,0 +1,32 @@
+//! Video processing (`VAEntrypointVideoProc`, `vtable_vpp`), not implemented yet.
+//!
+//! Nothing here is wired up: the entrypoint isn't advertised and `vtable_vpp` stays unset, so
+//! applications fall back to their own scaling and color conversion. Until then, this module
+//! collects what the pipeline has to support.
+
+// TODO: Requirements for the pipeline:
+// - VA_ROTATION_90/180/270 and VA_MIRROR_HORIZONTAL/VERTICAL from
+//   VAProcPipelineParameterBuffer, applied in a transform pass (compute, or a blit with
+//   swizzled offsets). Phone and camera recordings depend on it.
+// - Composition of several input surfaces onto one output in a single call, each with its own
+//   source/destination rectangle and alpha blending, as used by video walls and conferencing
+//   mixers.
+// - Scaling modes from the pipeline filter flags: VA_FILTER_SCALING_FAST via vkCmdBlitImage,
+//   VA_FILTER_SCALING_HQ and NL_ANAMORPHIC via a Lanczos/bicubic compute shader.
+// - VAProcFilter3DLUT: upload the LUT buffer as a 3D sampled image and apply it in the main
+//   VPP pass.
+// - VAProcFilterFrameRateConversion at a basic level (repeating or blending frames), so that
+//   e.g. 24 to 60 fps conversions don't fail config creation.
+// - forward_references/backward_references of VAProcPipelineParameterBuffer, in the numbers
+//   returned for the deinterlacing and frame-rate conversion filters by
+//   vaQueryVideoProcPipelineCaps (num_forward_references/num_backward_references). The
+//   neighbour surfaces must be kept readable (not recycled or written by decode) until the
+//   VPP work that samples them completes, and are bound alongside the current frame for
+//   motion-adaptive and motion-compensated deinterlacing.
+// - VAProcFilterSkinToneEnhancement and VAProcFilterTotalColorCorrection as simple compute
+//   filters. GStreamer's vapostproc queries them and fails negotiation if the filter list is
+//   refused.
+// Once filters exist, add golden-image tests: synthetic patterns (ramps, zone plates,
+// interlaced motion) through each filter (CSC, scaling, deinterlacing, procamp), compared
+// against stored reference images with a per-channel tolerance, so shader changes can't
+// silently alter the output.
@@ -0,0 +1,79 @@
+//! Timeout for waits on submitted pictures.
+//!
+//! A hung video queue (usually a driver or firmware bug, sometimes a malformed bitstream the
+//! hardware chokes on) would otherwise block the client in vaSyncSurface forever. Instead, the
+//! wait gives up after the watchdog period and reports an error, so the client can fall back to
+//! software decoding or tear down the pipeline.
+//!
+//! This does not recover anything: the timed-out submission is still pending, and the GPU still
+//! owns its command buffer, DPB and surfaces. Nothing it uses can be reused or destroyed until the
+//! queue drains or the device is lost, and the context has to fail every later picture.
+
+// Not wired up until pictures are submitted.
+#![allow(dead_code)]
+
+use std::{sync::LazyLock, time::Duration};
+
+use ash::vk;
+use log::{error, warn};
+
+use crate::{Operation, VaError};
+
+/// Environment variable setting after how many milliseconds a picture that hasn't completed is
+/// dropped. 0 disables the watchdog, the default is 5 seconds.
+const WATCHDOG_ENV: &str = "VAVK_WATCHDOG_MS";
+
+const DEFAULT_WATCHDOG: Duration = Duration::from_secs(5);
+
+static WATCHDOG: LazyLock<Option<Duration>> = LazyLock::new(|| {
+    let Ok(value) = std::env::var(WATCHDOG_ENV) else {
+        return Some(DEFAULT_WATCHDOG);
+    };
+    match value.trim().parse::<u64>() {
+        Ok(0) => None,
+        Ok(millis) => Some(Duration::from_millis(millis)),
+        Err(err) => {
+            warn!("Ignoring invalid {WATCHDOG_ENV}={value:?}: {err}");
+            Some(DEFAULT_WATCHDOG)
+        }
+    }
+});
+
+/// Waits until `semaphore` reaches `value`, the timeline value signalled by the submission of a
+/// picture.
+///
+/// If the watchdog period elapses first, returns DecodingError or EncodingError. The caller must
+/// then mark the context as failed: recording more work for it would only queue behind the hung
+/// submission, and its resources are still in use by the GPU.
+pub(crate) fn wait_for_picture(
+    device: &ash::Device,
+    semaphore: vk::Semaphore,
+    value: u64,
+    operation: Operation,
+) -> Result<(), VaError> {
+    let timeout = WATCHDOG.map_or(u64::MAX, |timeout| {
+        u64::try_from(timeout.as_nanos()).unwrap_or(u64::MAX)
+    });
+    let semaphores = [semaphore];
+    let values = [value];
+    let wait_info = vk::SemaphoreWaitInfo::default()
+        .semaphores(&semaphores)
+        .values(&values);
+    // SAFETY: The semaphore is a timeline semaphore created from `device`.
+    match unsafe { device.wait_semaphores(&wait_info, timeout) } {
+        Ok(()) => Ok(()),
+        Err(vk::Result::TIMEOUT) => {
+            error!(
+                "Picture {value} did not complete within {:?}, the device is unusable for this \
+                 context (raise {WATCHDOG_ENV} for very slow devices)",
+                WATCHDOG.unwrap_or_default(),
+            );
+            let err = match operation {
+                Operation::Decode => VaError::DecodingError,
+                Operation::Encode => VaError::EncodingError,
+            };
+            Err(err.context("Picture timed out"))
+        }
+        Err(result) => Err(result.into()),
+    }
+}
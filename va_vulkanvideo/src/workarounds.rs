@@ -0,0 +1,123 @@
+//! Workarounds for bugs in Vulkan video drivers, keyed on the driver and its version.
+//!
+//! Unlike [`crate::quirks`], which work around application behavior, these depend on the Vulkan
+//! implementation. Affected driver versions are listed in [`WORKAROUNDS`] rather than checked
+//! ad hoc where the behavior differs, so they are easy to review and to drop once the fixed
+//! versions are common. For testing on other drivers, workarounds can be forced on with a
+//! comma-separated list in `VAVK_WORKAROUNDS`, e.g. `VAVK_WORKAROUNDS=distinct_dpb`.
+//!
+//! The table is empty so far: no driver bug has been confirmed with an upstream report and a
+//! range of affected versions yet. Until then, `VAVK_WORKAROUNDS` is the only way to enable any
+//! of these.
+
+use ash::vk;
+use log::{info, warn};
+
+/// Environment variable with workarounds to enable regardless of the driver.
+const WORKAROUNDS_ENV: &str = "VAVK_WORKAROUNDS";
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct Workarounds {
+    /// Never decode into the DPB pictures directly, even if the implementation reports support
+    /// for coinciding DPB and output pictures.
+    pub(crate) distinct_dpb: bool,
+    /// Bytes of padding reserved after the bitstream in addition to the implementation's size
+    /// alignment, for drivers that read past the end of the range.
+    #[allow(dead_code)] // Used once bitstream buffers are allocated
+    pub(crate) bitstream_padding: vk::DeviceSize,
+}
+
+impl Workarounds {
+    /// Names for `VAVK_WORKAROUNDS`, with the workaround they enable.
+    const NAMED: [(&str, Self); 2] = [
+        (
+            "distinct_dpb",
+            Self {
+                distinct_dpb: true,
+                bitstream_padding: 0,
+            },
+        ),
+        (
+            "bitstream_padding",
+            Self {
+                distinct_dpb: false,
+                bitstream_padding: 256,
+            },
+        ),
+    ];
+
+    fn merge(self, other: Self) -> Self {
+        Self {
+            distinct_dpb: self.distinct_dpb || other.distinct_dpb,
+            bitstream_padding: self.bitstream_padding.max(other.bitstream_padding),
+        }
+    }
+}
+
+/// Workarounds for versions of a driver.
+struct DriverWorkarounds {
+    driver_id: vk::DriverId,
+    /// First affected `driverVersion` (in the driver's own encoding).
+    first: u32,
+    /// First fixed `driverVersion`, `None` if not fixed yet.
+    fixed: Option<u32>,
+    workarounds: Workarounds,
+}
+
+/// Known driver bugs. Add entries with a link to the upstream issue, and remove them once the
+/// fixed version is older than what distributions ship. Empty for now, see the module
+/// documentation.
+const WORKAROUNDS: [DriverWorkarounds; 0] = [];
+
+impl DriverWorkarounds {
+    fn applies(&self, driver_id: vk::DriverId, driver_version: u32) -> bool {
+        self.driver_id == driver_id
+            && driver_version >= self.first
+            && self.fixed.is_none_or(|fixed| driver_version < fixed)
+    }
+}
+
+/// Parses `VAVK_WORKAROUNDS`.
+fn forced() -> Workarounds {
+    let Ok(value) = std::env::var(WORKAROUNDS_ENV) else {
+        return Workarounds::default();
+    };
+    let mut workarounds = Workarounds::default();
+    for name in value
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        match Workarounds::NAMED.iter().find(|(n, _)| *n == name) {
+            Some((_, named)) => workarounds = workarounds.merge(*named),
+            None => warn!("Ignoring unknown workaround {name:?} in {WORKAROUNDS_ENV}"),
+        }
+    }
+    workarounds
+}
+
+/// Workarounds for the driver of `physical_device`.
+pub(crate) fn for_device(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+) -> Workarounds {
+    let mut driver_properties = vk::PhysicalDeviceDriverProperties::default();
+    let mut properties2 =
+        vk::PhysicalDeviceProperties2::default().push_next(&mut driver_properties);
+    unsafe {
+        instance.get_physical_device_properties2(physical_device, &mut properties2);
+    }
+    let driver_version = properties2.properties.driver_version;
+    let driver_id = driver_properties.driver_id;
+
+    let workarounds = WORKAROUNDS
+        .iter()
+        .filter(|entry| entry.applies(driver_id, driver_version))
+        .fold(forced(), |workarounds, entry| {
+            workarounds.merge(entry.workarounds)
+        });
+    if workarounds != Workarounds::default() {
+        info!("Workarounds for driver {driver_id:?} version {driver_version:#x}: {workarounds:?}");
+    }
+    workarounds
+}